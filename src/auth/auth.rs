@@ -0,0 +1,93 @@
+use std::sync::Arc;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+const AUTHORIZATION_HEADER: &str = "authorization";
+const BEARER: &str = "Bearer ";
+
+/// Rejects requests that don't carry one of known tokens, passes everything when there are none.
+#[derive(Clone)]
+pub struct ServerAuth {
+    tokens: Arc<[String]>,
+}
+
+impl ServerAuth {
+    pub fn new(tokens: Vec<String>) -> Self {
+        Self {
+            tokens: tokens.into(),
+        }
+    }
+}
+
+impl Interceptor for ServerAuth {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        if self.tokens.is_empty() {
+            return Ok(request);
+        }
+
+        let authorized =
+            bearer(&request).is_some_and(|candidate| is_one_of(candidate, &self.tokens));
+
+        if authorized {
+            Ok(request)
+        } else {
+            match request.remote_addr() {
+                Some(addr) => println!("unauthenticated request from: {}", addr),
+                None => println!("unauthenticated request from unknown address"),
+            }
+            Err(Status::unauthenticated("invalid or missing token"))
+        }
+    }
+}
+
+/// Attaches the shared token to every request.
+#[derive(Clone)]
+pub struct ClientAuth {
+    header: Option<MetadataValue<Ascii>>,
+}
+
+impl ClientAuth {
+    pub fn new(token: Option<String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let header = match token {
+            Some(token) => Some(format!("{}{}", BEARER, token).parse()?),
+            None => None,
+        };
+        Ok(Self { header })
+    }
+}
+
+impl Interceptor for ClientAuth {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(header) = &self.header {
+            request
+                .metadata_mut()
+                .insert(AUTHORIZATION_HEADER, header.clone());
+        }
+        Ok(request)
+    }
+}
+
+/// Token the request was made with.
+pub fn bearer<T>(request: &Request<T>) -> Option<&str> {
+    request
+        .metadata()
+        .get(AUTHORIZATION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix(BEARER))
+}
+
+/// Returns whether `candidate` equals any of `tokens`.
+pub fn is_one_of(candidate: &str, tokens: &[String]) -> bool {
+    tokens.iter().fold(false, |found, token| {
+        constant_time_eq(candidate.as_bytes(), token.as_bytes()) | found
+    })
+}
+
+/// Compares secrets without leaking position of the first mismatch through timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
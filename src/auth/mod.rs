@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+mod auth;
+
+pub use auth::{bearer, is_one_of, ClientAuth, ServerAuth};
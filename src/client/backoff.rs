@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+const INITIAL_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Exponential backoff between reconnection attempts.
+///
+/// Delays are jittered into `[delay / 2, delay)`, so clients dropped by the same server restart
+/// don't all reconnect at once.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    /// Delay before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = INITIAL_DELAY
+            .saturating_mul(1 << self.attempt.min(16))
+            .min(MAX_DELAY);
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half = delay / 2;
+        let jitter = getrandom::u64().unwrap_or_default() % (half.as_millis() as u64).max(1);
+        half + Duration::from_millis(jitter)
+    }
+
+    /// Starts over after connection has been established.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_within(delay: Duration, full: Duration) {
+        assert!(
+            delay >= full / 2 && delay < full,
+            "{:?} is not within [{:?}, {:?})",
+            delay,
+            full / 2,
+            full
+        );
+    }
+
+    #[test]
+    fn delay_grows_up_to_limit() {
+        let mut backoff = Backoff::default();
+        for attempt in 0..6 {
+            assert_within(backoff.next_delay(), INITIAL_DELAY * (1 << attempt));
+        }
+        for _ in 0..100 {
+            assert_within(backoff.next_delay(), MAX_DELAY);
+        }
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = Backoff::default();
+        for _ in 0..10 {
+            backoff.next_delay();
+        }
+        backoff.reset();
+        assert_within(backoff.next_delay(), INITIAL_DELAY);
+    }
+}
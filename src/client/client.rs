@@ -1,157 +1,285 @@
-use crate::clipboard::{CallbackResult, ClipboardHandler, ClipboardType, Master};
-use crate::message::clipboard_service_client::ClipboardServiceClient;
-use crate::message::Message;
-use arboard::{Clipboard, ImageData};
-use once_cell::sync::OnceCell;
-use std::borrow::Cow;
-use std::sync::atomic::{AtomicBool, Ordering};
+use crate::client::backoff::Backoff;
+use crate::client::Endpoint;
+use crate::clipboard::{
+    CallbackResult, ClipboardBackend, ClipboardHandler, ClipboardType, Selection, SelectionMode,
+};
+use crate::crypto::Cipher;
+use crate::echo::{Origin, Synced};
+use crate::handshake::{self, Capabilities, SESSION_HEADER};
+use crate::message::{Hello, Message};
+use crate::payload::{self, ClipboardItem, Incoming};
 use std::sync::Arc;
-use tokio::sync::Notify;
 use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
+use tonic::Code;
 
-static CLIPBOARD_LOCK: OnceCell<AtomicBool> = OnceCell::new();
-static STATE_LOCK: OnceCell<AtomicBool> = OnceCell::new();
+pub type Change = (Selection, ClipboardType);
+
+/// What client holds, lets server tell whether its latest content should be pushed.
+#[derive(Default)]
+struct Local {
+    /// Revision of the last content received from server, 0 if none.
+    revision: u64,
+    /// Local change happened while disconnected and hasn't been sent yet.
+    unsent: bool,
+}
 
 pub struct ClipboardClient {
-    host: String,
+    endpoint: Endpoint,
+    clipboard: Arc<dyn ClipboardBackend>,
+    selections: SelectionMode,
+    cipher: Cipher,
+    origin: Origin,
+    synced: Synced,
 }
 
-struct Handler {
-    sender: tokio::sync::mpsc::Sender<ClipboardType>,
+pub struct Handler {
+    sender: tokio::sync::mpsc::Sender<Change>,
+    selections: SelectionMode,
 }
 
 impl Handler {
-    pub fn new(sender: tokio::sync::mpsc::Sender<ClipboardType>) -> Self {
-        Self { sender }
+    pub fn new(sender: tokio::sync::mpsc::Sender<Change>, selections: SelectionMode) -> Self {
+        Self { sender, selections }
+    }
+
+    fn on_change(&mut self, selection: Selection, r#type: ClipboardType) -> CallbackResult {
+        // client is gone
+        if self.sender.blocking_send((selection, r#type)).is_err() {
+            return CallbackResult::Stop;
+        }
+        CallbackResult::Next
     }
 }
 
 impl ClipboardHandler for Handler {
     fn on_clipboard_change(&mut self, r#type: ClipboardType) -> CallbackResult {
-        if !STATE_LOCK.get().unwrap().load(Ordering::SeqCst) {
-            return CallbackResult::Stop;
-        }
+        self.on_change(Selection::Clipboard, r#type)
+    }
 
-        if let Err(e) = self.sender.blocking_send(r#type) {
-            eprintln!("send failed: {}", e);
-        }
-        CallbackResult::Next
+    fn on_primary_change(&mut self, r#type: ClipboardType) -> CallbackResult {
+        self.on_change(Selection::Primary, r#type)
+    }
+
+    fn selections(&self) -> SelectionMode {
+        self.selections
     }
 }
 
 impl ClipboardClient {
-    pub fn new(host: &str) -> Self {
-        CLIPBOARD_LOCK.set(AtomicBool::new(false)).ok();
-        STATE_LOCK.set(AtomicBool::new(true)).ok();
+    pub fn new(
+        endpoint: Endpoint,
+        clipboard: Arc<dyn ClipboardBackend>,
+        selections: SelectionMode,
+        cipher: Cipher,
+    ) -> Self {
         Self {
-            host: host.to_string(),
+            endpoint,
+            clipboard,
+            selections,
+            cipher,
+            origin: Origin::default(),
+            synced: Synced::default(),
         }
     }
 
+    /// Syncs clipboard until local watcher stops, reconnecting whenever connection is lost.
     pub async fn start(self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut client = ClipboardServiceClient::connect(format!("http://{}", self.host)).await?;
-        let (tx, rx) = tokio::sync::mpsc::channel(16);
-        let outbound = ReceiverStream::new(rx);
-        let mut stream = client.changed(outbound).await?.into_inner();
-        println!("successful connected to: {}", self.host);
-        let (sender, mut receiver) = tokio::sync::mpsc::channel::<ClipboardType>(1);
-        let close_notifier = Arc::new(Notify::new());
-
-        tokio::spawn(async move {
-            let mut clipboard = Clipboard::new().unwrap();
-            while let Ok(Some(msg)) = stream.message().await {
-                ClipboardClient::deal_message(&mut clipboard, msg);
+        let (sender, mut receiver) = tokio::sync::mpsc::channel::<Change>(16);
+        let selections = self.selections;
+        let clipboard = self.clipboard.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = clipboard.watch(Box::new(Handler::new(sender, selections))) {
+                eprintln!("clipboard watcher stopped: {}", e);
             }
-            println!("connection closed");
-            STATE_LOCK.get().unwrap().store(false, Ordering::SeqCst);
         });
 
-        tokio::spawn(async move {
-            let mut clipboard = Clipboard::new().unwrap();
-            while let Some(clipboard_type) = receiver.recv().await {
-                if let Some(lock) = CLIPBOARD_LOCK.get() {
-                    if !lock.load(Ordering::SeqCst) {
-                        match clipboard_type {
-                            ClipboardType::TEXT => {
-                                if let Ok(text) = clipboard.get_text() {
-                                    let tx = tx.clone();
-                                    tokio::spawn(async move {
-                                        tx.send(Message {
-                                            r#type: "text".to_owned(),
-                                            body: text.into_bytes(),
-                                        })
-                                        .await
-                                        .ok();
-                                    });
-                                }
-                            }
-                            ClipboardType::IMAGE => {
-                                if let Ok(image) = clipboard.get_image() {
-                                    if image.bytes.len() > 10 * 1024 * 1024 {
-                                        println!("image is too large: {}", image.bytes.len());
-                                        return;
-                                    }
-                                    let mut data = Vec::with_capacity(image.bytes.len() + 8);
-                                    data.extend_from_slice(&image.width.to_le_bytes());
-                                    data.extend_from_slice(&image.height.to_le_bytes());
-                                    data.extend_from_slice(&image.bytes);
-
-                                    let tx = tx.clone();
-                                    tokio::spawn(async move {
-                                        tx.send(Message {
-                                            r#type: "image".to_owned(),
-                                            body: data,
-                                        })
-                                        .await
-                                        .ok();
-                                    });
-                                }
+        let mut backoff = Backoff::default();
+        let mut local = Local::default();
+        loop {
+            if let Err(e) = self.session(&mut receiver, &mut backoff, &mut local).await {
+                if let Some(status) = e.downcast_ref::<tonic::Status>() {
+                    if matches!(
+                        status.code(),
+                        Code::Unauthenticated | Code::PermissionDenied | Code::FailedPrecondition
+                    ) {
+                        return Err(e);
+                    }
+                }
+                println!("failed to connect to {}: {}", self.endpoint.target, e);
+            }
+            if receiver.is_closed() {
+                return Ok(());
+            }
+
+            let delay = backoff.next_delay();
+            println!("reconnecting in {:.1}s", delay.as_secs_f32());
+            let sleep = tokio::time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    // changes made while offline are covered by resending current content
+                    change = receiver.recv() => match change {
+                        Some((selection, clipboard_type)) => {
+                            if self.local_change(selection, clipboard_type).is_some() {
+                                local.unsent = true;
                             }
-                            _ => {}
                         }
-                    } else {
-                        lock.store(false, Ordering::SeqCst);
-                    }
+                        None => return Ok(()),
+                    },
                 }
             }
-        });
+        }
+    }
 
-        let close_notifier_clone = close_notifier.clone();
-        std::thread::spawn(move || {
-            let mut master = Master::new(Handler::new(sender)).unwrap();
-            master.run().unwrap();
-            close_notifier_clone.notify_one();
-        });
+    /// Runs single connection until it is closed, `Err` means it could not be established.
+    async fn session(
+        &self,
+        receiver: &mut tokio::sync::mpsc::Receiver<Change>,
+        backoff: &mut Backoff,
+        local: &mut Local,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let host = self.endpoint.resolve().await?;
+        let mut client = self.endpoint.connect(&host).await?;
+        let hello = Hello {
+            channel: self.endpoint.channel.clone(),
+            last_revision: local.revision,
+            changed_offline: local.unsent,
+            ..handshake::hello()
+        };
+        let welcome = client.handshake(hello).await?.into_inner();
+        let server = welcome.server.ok_or("server did not describe itself")?;
+        let capabilities = Capabilities::from(&server);
 
-        close_notifier.notified().await;
-        Ok(())
-    }
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        let mut request = tonic::Request::new(ReceiverStream::new(rx));
+        request
+            .metadata_mut()
+            .insert(SESSION_HEADER, welcome.session_id.parse()?);
+        let mut stream = Incoming::new(client.changed(request).await?.into_inner());
+        println!(
+            "successful connected to: {} ({}, {})",
+            host, server.hostname, server.os
+        );
+        backoff.reset();
 
-    fn deal_message(clipboard: &mut Clipboard, msg: Message) {
-        match msg.r#type.as_ref() {
-            "text" => {
-                if let Ok(text) = String::from_utf8(msg.body) {
-                    if let Some(lock) = CLIPBOARD_LOCK.get() {
-                        lock.store(true, Ordering::SeqCst);
-                        clipboard.set_text(text).ok();
+        // single task sends local changes, so they reach the server in the order they were made
+        let (outgoing, mut queue) = tokio::sync::mpsc::unbounded_channel::<Message>();
+        tokio::spawn(async move {
+            while let Some(message) = queue.recv().await {
+                for chunk in payload::split(message) {
+                    if tx.send(chunk).await.is_err() {
+                        return;
                     }
                 }
             }
-            "image" => {
-                let w: [u8; 4] = msg.body[0..4].to_vec().try_into().unwrap();
-                let h: [u8; 4] = msg.body[4..8].to_vec().try_into().unwrap();
-                let width = u32::from_le_bytes(w) as usize;
-                let height = u32::from_le_bytes(h) as usize;
-                let image_data = ImageData {
-                    width,
-                    height,
-                    bytes: Cow::from(&msg.body[8..]),
-                };
-                clipboard.set_image(image_data).ok();
+        });
+        let send = |selection: Selection, item: ClipboardItem, content_hash: Vec<u8>| {
+            let message = self
+                .origin
+                .stamp(item.encode(selection, capabilities.encoding()));
+            let message = match self.cipher.seal(Message {
+                content_hash,
+                ..message
+            }) {
+                Ok(message) => message,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+            if capabilities.accepts(&message) {
+                outgoing.send(message).ok();
+            }
+        };
+
+        // whatever was copied while disconnected
+        let unsent = std::mem::take(&mut local.unsent);
+        for selection in [Selection::Clipboard, Selection::Primary] {
+            if !unsent || !self.selections.contains(selection) {
+                continue;
+            }
+            if let Some(item) = self.clipboard.get(selection) {
+                let hash = item.content_hash();
+                send(selection, item, hash);
+            }
+        }
+
+        loop {
+            tokio::select! {
+                message = stream.message() => match message {
+                    Ok(Some(msg)) => {
+                        local.revision = local.revision.max(msg.revision);
+                        self.deal_message(msg)
+                    }
+                    Ok(None) => {
+                        println!("connection closed");
+                        return Ok(());
+                    }
+                    Err(status) => {
+                        println!("connection lost: {}", status.message());
+                        return Ok(());
+                    }
+                },
+                change = receiver.recv() => {
+                    let Some((selection, clipboard_type)) = change else {
+                        return Ok(());
+                    };
+                    if let Some((item, hash)) = self.local_change(selection, clipboard_type) {
+                        send(selection, item, hash);
+                    }
+                }
             }
-            _ => {
-                println!("not supported type: {}", msg.r#type);
+        }
+    }
+
+    /// Reads content copied on this machine, `None` if the change was caused by writing received one.
+    fn local_change(
+        &self,
+        selection: Selection,
+        r#type: ClipboardType,
+    ) -> Option<(ClipboardItem, Vec<u8>)> {
+        if matches!(r#type, ClipboardType::UNKNOWN) {
+            return None;
+        }
+        let item = self.clipboard.get(selection)?;
+        let hash = item.content_hash();
+        let origin = self
+            .synced
+            .changed(selection, hash.clone(), self.origin.id())?;
+        (origin == self.origin.id()).then_some((item, hash))
+    }
+
+    fn deal_message(&self, msg: Message) {
+        let selection = Selection::from(msg.selection());
+        // own content sent back, or content clipboard already holds
+        if msg.origin == self.origin.id()
+            || (!msg.content_hash.is_empty()
+                && self.synced.is_current(selection, &msg.content_hash))
+        {
+            return;
+        }
+        let origin = msg.origin.clone();
+        let (selection, item) = match self.cipher.open(msg).and_then(ClipboardItem::decode) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                println!("invalid message: {}", e);
+                return;
             }
+        };
+        if !self.selections.contains(selection) || !self.clipboard.supports(selection) {
+            return;
+        }
+
+        if !self
+            .synced
+            .received(selection, item.content_hash(), &origin)
+        {
+            return;
+        }
+        if let Err(e) = self.clipboard.set(selection, item) {
+            self.synced.forget(selection);
+            println!("{}", e);
         }
     }
 }
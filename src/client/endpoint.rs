@@ -0,0 +1,136 @@
+use crate::auth::ClientAuth;
+use crate::discovery::{self, DISCOVERY_PORT};
+use crate::handshake::DEFAULT_CHANNEL;
+use crate::message::clipboard_service_client::ClipboardServiceClient;
+use crate::payload::max_message_size;
+use crate::tls::ClientTlsArgs;
+use clap::Args;
+use std::fmt;
+use std::time::Duration;
+use tonic::codec::CompressionEncoding;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::{Channel, ClientTlsConfig};
+
+pub type ServiceClient = ClipboardServiceClient<InterceptedService<Channel, ClientAuth>>;
+
+/// How long to wait for discovery answers before connecting.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where the server is and how to talk to it.
+#[derive(Clone)]
+pub struct Endpoint {
+    pub target: Target,
+    pub token: Option<String>,
+    pub channel: String,
+    pub tls: Option<ClientTlsConfig>,
+    /// Compress gRPC messages with gzip, server must be of the version that accepts it.
+    pub gzip: bool,
+}
+
+/// Command line options describing how to reach the server.
+#[derive(Args, Debug, Clone)]
+pub struct ConnectArgs {
+    /// Server address as `host:port`
+    #[arg(short, long, required_unless_present = "discover")]
+    pub addr: Option<String>,
+    /// Find server on local network instead of connecting to `--addr`
+    #[arg(long, conflicts_with = "addr")]
+    pub discover: bool,
+    /// UDP port servers answer discovery probes on
+    #[arg(long, default_value_t = DISCOVERY_PORT)]
+    pub discovery_port: u16,
+    /// Shared secret expected by the server
+    #[arg(long, env = "LANCLIP_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+    /// Channel to join, clipboard is shared only with clients of the same channel
+    #[arg(long, default_value = DEFAULT_CHANNEL)]
+    pub channel: String,
+    #[command(flatten)]
+    pub tls: ClientTlsArgs,
+    /// Compress whole gRPC messages with gzip, on top of payload compression
+    #[arg(long)]
+    pub grpc_gzip: bool,
+}
+
+impl ConnectArgs {
+    pub fn endpoint(self) -> Result<Endpoint, Box<dyn std::error::Error>> {
+        let tls = self.tls.config(self.addr.as_deref().unwrap_or_default())?;
+        let target = match self.addr {
+            Some(addr) if !self.discover => Target::Address(addr),
+            _ => Target::Discover(self.discovery_port),
+        };
+        Ok(Endpoint {
+            target,
+            token: self.token,
+            channel: self.channel,
+            tls,
+            gzip: self.grpc_gzip,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub enum Target {
+    /// Fixed `host:port`.
+    Address(String),
+    /// First server answering probes on given UDP port.
+    Discover(u16),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Address(host) => write!(f, "{}", host),
+            Target::Discover(port) => write!(f, "server discovered on udp port {}", port),
+        }
+    }
+}
+
+impl Endpoint {
+    /// Finds `host:port` to connect to, discovery is repeated on every call so that server
+    /// that changed its address is found again.
+    pub async fn resolve(&self) -> Result<String, Box<dyn std::error::Error>> {
+        match &self.target {
+            Target::Address(host) => Ok(host.clone()),
+            Target::Discover(port) => {
+                let servers = discovery::discover(*port, DISCOVERY_TIMEOUT).await?;
+                let server = servers.first().ok_or("no server found on local network")?;
+                if servers.len() > 1 {
+                    println!(
+                        "found {} servers, using {} ({})",
+                        servers.len(),
+                        server.addr,
+                        server.announcement.hostname
+                    );
+                }
+                Ok(server.addr.to_string())
+            }
+        }
+    }
+
+    pub async fn connect(&self, host: &str) -> Result<ServiceClient, Box<dyn std::error::Error>> {
+        let channel = match &self.tls {
+            Some(tls) => {
+                Channel::from_shared(format!("https://{}", host))?
+                    .tls_config(tls.clone())?
+                    .connect()
+                    .await?
+            }
+            None => {
+                Channel::from_shared(format!("http://{}", host))?
+                    .connect()
+                    .await?
+            }
+        };
+        let mut client =
+            ClipboardServiceClient::with_interceptor(channel, ClientAuth::new(self.token.clone())?)
+                .max_decoding_message_size(max_message_size())
+                .max_encoding_message_size(max_message_size());
+        if self.gzip {
+            client = client
+                .send_compressed(CompressionEncoding::Gzip)
+                .accept_compressed(CompressionEncoding::Gzip);
+        }
+        Ok(client)
+    }
+}
@@ -1,3 +1,7 @@
+pub mod backoff;
+#[allow(clippy::module_inception)]
 pub mod client;
+pub mod endpoint;
 
-pub use client::ClipboardClient;
\ No newline at end of file
+pub use client::ClipboardClient;
+pub use endpoint::{ConnectArgs, Endpoint, Target};
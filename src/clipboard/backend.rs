@@ -0,0 +1,54 @@
+use crate::clipboard::{ClipboardHandler, Master, Selection};
+use crate::payload::{ClipboardItem, PayloadError};
+use arboard::Clipboard;
+use std::io;
+use std::sync::Mutex;
+
+///Where clipboard content is read from and written to.
+///
+///Components only talk to clipboard through it, so tests can replace system one with memory.
+pub trait ClipboardBackend: Send + Sync {
+    ///Returns whether `selection` exists, PRIMARY is only available on Linux.
+    fn supports(&self, selection: Selection) -> bool;
+    ///Reads every representation of `selection`, `None` if there is nothing to send.
+    fn get(&self, selection: Selection) -> Option<ClipboardItem>;
+    ///Replaces content of `selection` with `item`.
+    fn set(&self, selection: Selection, item: ClipboardItem) -> Result<(), PayloadError>;
+    ///Calls `handler` on every change until it stops, blocking current thread.
+    fn watch(&self, handler: Box<dyn ClipboardHandler + Send>) -> io::Result<()>;
+}
+
+///Clipboard of this machine, changes are tracked by platform `Master`.
+pub struct SystemClipboard {
+    clipboard: Mutex<Clipboard>,
+}
+
+impl SystemClipboard {
+    pub fn new() -> Result<Self, arboard::Error> {
+        Ok(Self {
+            clipboard: Mutex::new(Clipboard::new()?),
+        })
+    }
+}
+
+impl ClipboardBackend for SystemClipboard {
+    fn supports(&self, selection: Selection) -> bool {
+        selection.get(&mut self.clipboard.lock().unwrap()).is_some()
+    }
+
+    fn get(&self, selection: Selection) -> Option<ClipboardItem> {
+        ClipboardItem::read(&mut self.clipboard.lock().unwrap(), selection)
+    }
+
+    fn set(&self, selection: Selection, item: ClipboardItem) -> Result<(), PayloadError> {
+        let mut clipboard = self.clipboard.lock().unwrap();
+        let set = selection
+            .set(&mut clipboard)
+            .ok_or(PayloadError::Unsupported("selection"))?;
+        item.write(selection, set)
+    }
+
+    fn watch(&self, handler: Box<dyn ClipboardHandler + Send>) -> io::Result<()> {
+        Master::new(handler)?.run()
+    }
+}
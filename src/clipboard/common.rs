@@ -1,13 +1,19 @@
-use crate::clipboard::Shutdown;
 /**
 * Copyright (c) 2015 Douman
 */
+use crate::clipboard::{SelectionMode, Shutdown};
 use std::io;
 
 ///Describes Clipboard handler
 pub trait ClipboardHandler {
     ///Callback to call on clipboard change.
     fn on_clipboard_change(&mut self, r#type: ClipboardType) -> CallbackResult;
+    ///Callback to call on PRIMARY selection change.
+    ///
+    ///Only invoked on Linux when `selections` includes PRIMARY.
+    fn on_primary_change(&mut self, _type: ClipboardType) -> CallbackResult {
+        CallbackResult::Next
+    }
     ///Callback to call on when error happens in master.
     fn on_clipboard_error(&mut self, error: io::Error) -> CallbackResult {
         CallbackResult::StopWithError(error)
@@ -20,6 +26,32 @@ pub trait ClipboardHandler {
     fn sleep_interval(&self) -> core::time::Duration {
         core::time::Duration::from_millis(500)
     }
+
+    #[inline(always)]
+    ///Returns selections to watch on platforms that have several (e.g. X11/Wayland).
+    ///
+    ///Default value is CLIPBOARD only
+    fn selections(&self) -> SelectionMode {
+        SelectionMode::Clipboard
+    }
+}
+
+impl<H: ClipboardHandler + ?Sized> ClipboardHandler for Box<H> {
+    fn on_clipboard_change(&mut self, r#type: ClipboardType) -> CallbackResult {
+        (**self).on_clipboard_change(r#type)
+    }
+    fn on_primary_change(&mut self, r#type: ClipboardType) -> CallbackResult {
+        (**self).on_primary_change(r#type)
+    }
+    fn on_clipboard_error(&mut self, error: io::Error) -> CallbackResult {
+        (**self).on_clipboard_error(error)
+    }
+    fn sleep_interval(&self) -> core::time::Duration {
+        (**self).sleep_interval()
+    }
+    fn selections(&self) -> SelectionMode {
+        (**self).selections()
+    }
 }
 
 ///Possible return values of callback.
@@ -32,17 +64,91 @@ pub enum CallbackResult {
     StopWithError(io::Error),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ClipboardType {
     IMAGE,
     TEXT,
+    HTML,
     FILE,
     UNKNOWN,
 }
 
+impl ClipboardType {
+    ///Picks content type from list of targets (MIME types or X11 atom names) offered by owner.
+    ///
+    ///Files take precedence over images, images over HTML and HTML over text, as file managers,
+    ///image viewers and browsers usually offer simpler representations too.
+    pub fn from_targets<'a>(targets: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut result = ClipboardType::UNKNOWN;
+        for target in targets {
+            match target {
+                "text/uri-list" | "x-special/gnome-copied-files" => return ClipboardType::FILE,
+                target if target.starts_with("image/") => result = ClipboardType::IMAGE,
+                "text/html" if !matches!(result, ClipboardType::IMAGE) => {
+                    result = ClipboardType::HTML
+                }
+                "UTF8_STRING" | "STRING" | "TEXT" | "text/plain" | "text/plain;charset=utf-8"
+                    if matches!(result, ClipboardType::UNKNOWN) =>
+                {
+                    result = ClipboardType::TEXT
+                }
+                _ => {}
+            }
+        }
+        result
+    }
+}
+
 impl Shutdown {
     ///Signals shutdown
+    #[allow(dead_code)]
     pub fn signal(self) {
         drop(self);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn richest_target_wins() {
+        let browser = ["TARGETS", "UTF8_STRING", "text/html", "text/plain"];
+        assert!(matches!(
+            ClipboardType::from_targets(browser),
+            ClipboardType::HTML
+        ));
+
+        let viewer = ["text/html", "image/png", "UTF8_STRING"];
+        assert!(matches!(
+            ClipboardType::from_targets(viewer),
+            ClipboardType::IMAGE
+        ));
+
+        let file_manager = ["UTF8_STRING", "image/png", "x-special/gnome-copied-files"];
+        assert!(matches!(
+            ClipboardType::from_targets(file_manager),
+            ClipboardType::FILE
+        ));
+
+        let editor = ["TARGETS", "TIMESTAMP", "STRING"];
+        assert!(matches!(
+            ClipboardType::from_targets(editor),
+            ClipboardType::TEXT
+        ));
+    }
+
+    #[test]
+    fn unknown_targets_are_unknown() {
+        assert!(matches!(
+            ClipboardType::from_targets([]),
+            ClipboardType::UNKNOWN
+        ));
+        let targets = ["TARGETS", "application/x-qt-windows-mime"];
+        assert!(matches!(
+            ClipboardType::from_targets(targets),
+            ClipboardType::UNKNOWN
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+use crate::clipboard::common::ClipboardHandler;
+use crate::clipboard::{owner, wayland, x11, Selection};
+
+use std::env;
+use std::io;
+
+///Shutdown channel
+///
+///On drop requests shutdown to gracefully close clipboard listener as soon as possible.
+#[allow(dead_code)]
+pub enum Shutdown {
+    X11(x11::Shutdown),
+    Wayland(wayland::Shutdown),
+}
+
+///Clipboard master.
+///
+///Tracks changes of clipboard and invokes corresponding callbacks.
+///
+///# Platform notes:
+///
+///- On `linux` backend is chosen at runtime: Wayland when `WAYLAND_DISPLAY` is set, X11 otherwise.
+pub enum Master<H> {
+    X11(x11::Master<H>),
+    Wayland(wayland::Master<H>),
+}
+
+impl<H: ClipboardHandler> Master<H> {
+    #[inline(always)]
+    ///Creates new instance.
+    pub fn new(handler: H) -> io::Result<Self> {
+        if is_wayland() {
+            wayland::Master::new(handler).map(Master::Wayland)
+        } else {
+            x11::Master::new(handler).map(Master::X11)
+        }
+    }
+
+    #[inline(always)]
+    ///Creates shutdown channel.
+    #[allow(dead_code)]
+    pub fn shutdown_channel(&self) -> Shutdown {
+        match self {
+            Master::X11(master) => Shutdown::X11(master.shutdown_channel()),
+            Master::Wayland(master) => Shutdown::Wayland(master.shutdown_channel()),
+        }
+    }
+
+    ///Starts Master of selected backend
+    pub fn run(&mut self) -> io::Result<()> {
+        match self {
+            Master::X11(master) => master.run(),
+            Master::Wayland(master) => master.run(),
+        }
+    }
+}
+
+///Returns whether current session is Wayland one.
+pub fn is_wayland() -> bool {
+    env::var_os("WAYLAND_DISPLAY").is_some_and(|display| !display.is_empty())
+}
+
+///Places content into `selection`, every target with its own data, served by this process until
+///something else is copied.
+///
+///`wait` blocks until then.
+pub fn offer(selection: Selection, targets: Vec<(&'static str, Vec<u8>)>, wait: bool) -> io::Result<()> {
+    if !is_wayland() {
+        return owner::offer(selection, targets, wait);
+    }
+
+    use wl_clipboard_rs::copy::{ClipboardType, MimeSource, MimeType, Options, Source};
+    let mut options = Options::new();
+    options.clipboard(match selection {
+        Selection::Clipboard => ClipboardType::Regular,
+        Selection::Primary => ClipboardType::Primary,
+    });
+    options.foreground(wait);
+    let sources = targets
+        .into_iter()
+        // X11 only targets are not MIME types
+        .filter(|(target, _)| target.contains('/'))
+        .map(|(target, content)| MimeSource {
+            source: Source::Bytes(content.into_boxed_slice()),
+            mime_type: MimeType::Specific(target.to_owned()),
+        })
+        .collect();
+    options.copy_multi(sources).map_err(io::Error::other)
+}
@@ -6,10 +6,13 @@ use std::sync::mpsc::{self, sync_channel, Receiver, SyncSender};
 
 use crate::clipboard::common::{CallbackResult, ClipboardHandler, ClipboardType};
 use objc2::rc::Retained;
+use objc2::runtime::ProtocolObject;
 use objc2::{msg_send, ClassType};
 use objc2_app_kit::{
-    NSPasteboard, NSPasteboardTypePNG, NSPasteboardTypeString, NSPasteboardTypeTIFF,
+    NSPasteboard, NSPasteboardItem, NSPasteboardType, NSPasteboardTypeFileURL, NSPasteboardTypeHTML, NSPasteboardTypePNG,
+    NSPasteboardTypeRTF, NSPasteboardTypeString, NSPasteboardTypeTIFF, NSPasteboardWriting,
 };
+use objc2_foundation::{NSArray, NSData};
 
 #[link(name = "AppKit", kind = "framework")]
 extern "C" {}
@@ -17,6 +20,7 @@ extern "C" {}
 ///Shutdown channel
 ///
 ///On drop requests shutdown to gracefully close clipboard listener as soon as possible.
+#[allow(dead_code)]
 pub struct Shutdown {
     sender: SyncSender<()>,
 }
@@ -37,6 +41,7 @@ impl Drop for Shutdown {
 ///- On `windows` it creates dummy window that monitors each clipboard change message.
 pub struct Master<H> {
     handler: H,
+    #[allow(dead_code)]
     sender: SyncSender<()>,
     recv: Receiver<()>,
 }
@@ -56,6 +61,7 @@ impl<H: ClipboardHandler> Master<H> {
 
     #[inline(always)]
     ///Creates shutdown channel.
+    #[allow(dead_code)]
     pub fn shutdown_channel(&self) -> Shutdown {
         Shutdown {
             sender: self.sender.clone(),
@@ -95,15 +101,26 @@ impl<H: ClipboardHandler> Master<H> {
             prev_count = count;
 
             let mut clipboard_type = ClipboardType::UNKNOWN;
+            // richest type offered by any of the items
             if let Some(items) = unsafe { pasteboard.pasteboardItems() } {
-                if let Some(item) = items.firstObject() {
+                for item in items.iter() {
                     unsafe {
                         let types = item.types();
-                        if types.containsObject(NSPasteboardTypeTIFF)
+                        if types.containsObject(NSPasteboardTypeFileURL) {
+                            clipboard_type = ClipboardType::FILE
+                        } else if types.containsObject(NSPasteboardTypeTIFF) || types.containsObject(NSPasteboardTypePNG)
                         {
-                            clipboard_type = ClipboardType::IMAGE
+                            if !matches!(clipboard_type, ClipboardType::FILE) {
+                                clipboard_type = ClipboardType::IMAGE
+                            }
+                        } else if types.containsObject(NSPasteboardTypeHTML) {
+                            if matches!(clipboard_type, ClipboardType::UNKNOWN | ClipboardType::TEXT) {
+                                clipboard_type = ClipboardType::HTML
+                            }
                         } else if types.containsObject(NSPasteboardTypeString) {
-                            clipboard_type = ClipboardType::TEXT
+                            if matches!(clipboard_type, ClipboardType::UNKNOWN) {
+                                clipboard_type = ClipboardType::TEXT
+                            }
                         }
                     }
                 }
@@ -127,3 +144,58 @@ impl<H: ClipboardHandler> Master<H> {
         result
     }
 }
+
+///Replaces content of general pasteboard, every target (MIME type) is written as its pasteboard type.
+///
+///Targets without pasteboard type are skipped, every file of file list gets its own pasteboard item.
+pub fn offer(targets: Vec<(&'static str, Vec<u8>)>) -> io::Result<()> {
+    let pasteboard: Option<Retained<NSPasteboard>> =
+        unsafe { msg_send![NSPasteboard::class(), generalPasteboard] };
+    let pasteboard = pasteboard.ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Unable to create mac pasteboard"))?;
+
+    //first item holds every other type too
+    let mut items = vec![unsafe { NSPasteboardItem::new() }];
+    for (target, content) in targets {
+        let r#type = unsafe {
+            match target {
+                "text/plain;charset=utf-8" => NSPasteboardTypeString,
+                "text/html" => NSPasteboardTypeHTML,
+                "text/rtf" => NSPasteboardTypeRTF,
+                "image/png" => NSPasteboardTypePNG,
+                "text/uri-list" => {
+                    let uris = content
+                        .split(|&byte| byte == b'\n')
+                        .map(|uri| uri.strip_suffix(b"\r").unwrap_or(uri))
+                        .filter(|uri| !uri.is_empty());
+                    for (index, uri) in uris.enumerate() {
+                        if index == items.len() {
+                            items.push(NSPasteboardItem::new());
+                        }
+                        set_data(&items[index], NSPasteboardTypeFileURL, uri)?;
+                    }
+                    continue;
+                }
+                _ => continue,
+            }
+        };
+        set_data(&items[0], r#type, &content)?;
+    }
+
+    let objects: Vec<Retained<ProtocolObject<dyn NSPasteboardWriting>>> =
+        items.into_iter().map(ProtocolObject::from_retained).collect();
+    let objects = NSArray::from_retained_slice(&objects);
+    unsafe { pasteboard.clearContents() };
+    if !unsafe { pasteboard.writeObjects(&objects) } {
+        return Err(io::Error::new(io::ErrorKind::Other, "Failed to write into pasteboard"));
+    }
+    Ok(())
+}
+
+fn set_data(item: &NSPasteboardItem, r#type: &NSPasteboardType, content: &[u8]) -> io::Result<()> {
+    let data = NSData::with_bytes(content);
+    if unsafe { item.setData_forType(&data, r#type) } {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::Other, "Failed to write into pasteboard item"))
+    }
+}
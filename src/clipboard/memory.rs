@@ -0,0 +1,87 @@
+use crate::clipboard::{
+    CallbackResult, ClipboardBackend, ClipboardHandler, ClipboardType, Selection,
+};
+use crate::payload::{ClipboardItem, Payload, PayloadError};
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+
+///Clipboard kept in memory, every `set` is reported to watchers as system one would do.
+#[derive(Default)]
+pub struct MemoryClipboard {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    contents: HashMap<Selection, ClipboardItem>,
+    writes: usize,
+    watchers: Vec<Sender<(Selection, ClipboardType)>>,
+}
+
+impl MemoryClipboard {
+    ///Returns whether somebody started watching changes, earlier ones are not reported.
+    pub fn is_watched(&self) -> bool {
+        !self.state.lock().unwrap().watchers.is_empty()
+    }
+
+    ///Number of `set` calls so far.
+    pub fn writes(&self) -> usize {
+        self.state.lock().unwrap().writes
+    }
+
+    ///Text pasted from `selection`, `None` if its richest content is not a text.
+    pub fn text(&self, selection: Selection) -> Option<String> {
+        match self.get(selection)?.richest()? {
+            Payload::Text(text) => Some(text.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl ClipboardBackend for MemoryClipboard {
+    fn supports(&self, _selection: Selection) -> bool {
+        true
+    }
+
+    fn get(&self, selection: Selection) -> Option<ClipboardItem> {
+        self.state.lock().unwrap().contents.get(&selection).cloned()
+    }
+
+    fn set(&self, selection: Selection, item: ClipboardItem) -> Result<(), PayloadError> {
+        let r#type = match item.richest().ok_or(PayloadError::Empty)? {
+            Payload::Files(_) => ClipboardType::FILE,
+            Payload::Image(_) => ClipboardType::IMAGE,
+            Payload::Html { .. } => ClipboardType::HTML,
+            Payload::Text(_) | Payload::Rtf { .. } => ClipboardType::TEXT,
+        };
+        let mut state = self.state.lock().unwrap();
+        state.contents.insert(selection, item);
+        state.writes += 1;
+        state
+            .watchers
+            .retain(|watcher| watcher.send((selection, r#type.clone())).is_ok());
+        Ok(())
+    }
+
+    fn watch(&self, mut handler: Box<dyn ClipboardHandler + Send>) -> io::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        self.state.lock().unwrap().watchers.push(sender);
+        for (selection, r#type) in receiver {
+            let result = match selection {
+                Selection::Clipboard => handler.on_clipboard_change(r#type),
+                Selection::Primary if handler.selections().contains(selection) => {
+                    handler.on_primary_change(r#type)
+                }
+                Selection::Primary => continue,
+            };
+            match result {
+                CallbackResult::Next => (),
+                CallbackResult::Stop => break,
+                CallbackResult::StopWithError(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+}
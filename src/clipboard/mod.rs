@@ -1,18 +1,35 @@
+mod backend;
 mod common;
+#[cfg(test)]
+mod memory;
+mod selection;
 
 #[cfg(windows)]
 mod win32;
 #[cfg(windows)]
 pub use win32::{Master, Shutdown};
 
+//vendored backends keep their original error construction
 #[cfg(all(unix, not(any(target_os="macos", target_os="ios", target_os="android", target_os="emscripten"))))]
+#[allow(clippy::io_other_error)]
 mod x11;
 #[cfg(all(unix, not(any(target_os="macos", target_os="ios", target_os="android", target_os="emscripten"))))]
-pub use x11::{Master, Shutdown};
+mod wayland;
+#[cfg(all(unix, not(any(target_os="macos", target_os="ios", target_os="android", target_os="emscripten"))))]
+mod linux;
+#[cfg(all(unix, not(any(target_os="macos", target_os="ios", target_os="android", target_os="emscripten"))))]
+mod owner;
+#[cfg(all(unix, not(any(target_os="macos", target_os="ios", target_os="android", target_os="emscripten"))))]
+pub use linux::{Master, Shutdown};
 
 #[cfg(target_os = "macos")]
+#[allow(clippy::io_other_error)]
 mod mac;
 #[cfg(target_os = "macos")]
 pub use mac::{Master, Shutdown};
 
+pub use backend::{ClipboardBackend, SystemClipboard};
 pub use common::{CallbackResult, ClipboardHandler, ClipboardType};
+#[cfg(test)]
+pub use memory::MemoryClipboard;
+pub use selection::{Selection, SelectionMode};
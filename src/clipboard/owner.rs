@@ -0,0 +1,392 @@
+use crate::clipboard::Selection;
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use x11rb::connection::Connection;
+use x11rb::errors::ReplyOrIdError;
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt, CreateWindowAux, EventMask, PropMode,
+    Property, PropertyNotifyEvent, SelectionNotifyEvent, SelectionRequestEvent, Timestamp, Window,
+    WindowClass, SELECTION_NOTIFY_EVENT,
+};
+use x11rb::protocol::{ErrorKind, Event};
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::{COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT, NONE};
+
+///Content larger than this is sent in chunks using INCR protocol.
+const INCR_CHUNK_SIZE: usize = 64 * 1024;
+
+///How long to wait for X server to tell the time ownership is taken at.
+const TIMESTAMP_TIMEOUT: Duration = Duration::from_secs(1);
+
+///Targets offered in a selection with their content.
+type Offer = Vec<(Atom, Arc<[u8]>)>;
+
+///X11 selection owner offering content as several targets at once.
+///
+///Requests are answered by background thread for as long as process lives and nobody else takes
+///the selection.
+struct Owner {
+    connection: RustConnection,
+    window: Window,
+    clipboard: Atom,
+    targets: Atom,
+    incr: Atom,
+    timestamp: Atom,
+    state: Mutex<State>,
+    replaced: Condvar,
+    stamped: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    ///Offer of every selection owned, numbered to tell the newer one of the same selection
+    offers: HashMap<Atom, (u64, Offer)>,
+    offered: u64,
+    ///Server time of the last change of `timestamp` property of our window
+    time: Option<Timestamp>,
+}
+
+///What requestor of a target gets.
+enum Answer {
+    Targets(Vec<Atom>),
+    Content(Arc<[u8]>),
+}
+
+///Content sent in chunks, one every time requestor deletes the property.
+struct Transfer {
+    target: Atom,
+    content: Arc<[u8]>,
+    sent: usize,
+}
+
+///Takes ownership of `selection` offering every target with its own content.
+///
+///`wait` blocks until something else is copied.
+pub fn offer(
+    selection: Selection,
+    targets: Vec<(&'static str, Vec<u8>)>,
+    wait: bool,
+) -> io::Result<()> {
+    let owner = owner()?;
+    let selection = match selection {
+        Selection::Clipboard => owner.clipboard,
+        Selection::Primary => AtomEnum::PRIMARY.into(),
+    };
+    let mut offer = Vec::with_capacity(targets.len());
+    for (target, content) in targets {
+        offer.push((
+            intern(&owner.connection, target).map_err(io::Error::other)?,
+            Arc::from(content),
+        ));
+    }
+
+    let number = {
+        let mut state = owner.state.lock().unwrap();
+        state.offered += 1;
+        let number = state.offered;
+        state.offers.insert(selection, (number, offer));
+        number
+    };
+    if !owner.take(selection).map_err(io::Error::other)? {
+        owner.withdraw(selection, number);
+        return Err(io::Error::other("failed to take ownership of selection"));
+    }
+
+    if wait {
+        let state = owner.state.lock().unwrap();
+        let _state = owner
+            .replaced
+            .wait_while(state, |state| {
+                state
+                    .offers
+                    .get(&selection)
+                    .is_some_and(|(offered, _)| *offered == number)
+            })
+            .unwrap();
+    }
+    Ok(())
+}
+
+///Returns owner shared by the whole process, starts serving it on first call.
+fn owner() -> io::Result<Arc<Owner>> {
+    static OWNER: OnceLock<Result<Arc<Owner>, String>> = OnceLock::new();
+    OWNER
+        .get_or_init(|| {
+            let owner = Arc::new(
+                Owner::new()
+                    .map_err(|error| format!("Failed to initialize clipboard: {}", error))?,
+            );
+            let serving = owner.clone();
+            thread::spawn(move || {
+                if let Err(error) = serving.serve() {
+                    println!("clipboard owner stopped: {}", error);
+                }
+            });
+            Ok(owner)
+        })
+        .clone()
+        .map_err(io::Error::other)
+}
+
+fn intern(connection: &RustConnection, name: &str) -> Result<Atom, ReplyOrIdError> {
+    Ok(connection
+        .intern_atom(false, name.as_bytes())?
+        .reply()?
+        .atom)
+}
+
+impl Owner {
+    fn new() -> io::Result<Self> {
+        let (connection, screen) = x11rb::connect(None).map_err(io::Error::other)?;
+        Self::create(connection, screen).map_err(io::Error::other)
+    }
+
+    fn create(connection: RustConnection, screen: usize) -> Result<Self, ReplyOrIdError> {
+        let window = connection.generate_id()?;
+        let root = connection.setup().roots[screen].root;
+        connection.create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            COPY_FROM_PARENT,
+            &CreateWindowAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )?;
+        let clipboard = intern(&connection, "CLIPBOARD")?;
+        let targets = intern(&connection, "TARGETS")?;
+        let incr = intern(&connection, "INCR")?;
+        let timestamp = intern(&connection, "LANCLIP_TIMESTAMP")?;
+        connection.flush()?;
+
+        Ok(Self {
+            connection,
+            window,
+            clipboard,
+            targets,
+            incr,
+            timestamp,
+            state: Mutex::default(),
+            replaced: Condvar::new(),
+            stamped: Condvar::new(),
+        })
+    }
+
+    ///Becomes owner of `selection`, returns whether server agreed.
+    fn take(&self, selection: Atom) -> Result<bool, ReplyOrIdError> {
+        let Some(time) = self.now()? else {
+            return Ok(false);
+        };
+        self.connection
+            .set_selection_owner(self.window, selection, time)?;
+        Ok(self
+            .connection
+            .get_selection_owner(selection)?
+            .reply()?
+            .owner
+            == self.window)
+    }
+
+    ///Current server time, ICCCM forbids taking ownership at `CurrentTime`.
+    ///
+    ///Server tells it in notification of appending nothing to a property of our window, `None`
+    ///if the notification doesn't come.
+    fn now(&self) -> Result<Option<Timestamp>, ReplyOrIdError> {
+        let mut state = self.state.lock().unwrap();
+        state.time = None;
+        self.connection.change_property8(
+            PropMode::APPEND,
+            self.window,
+            self.timestamp,
+            AtomEnum::STRING,
+            &[],
+        )?;
+        self.connection.flush()?;
+        let (state, _) = self
+            .stamped
+            .wait_timeout_while(state, TIMESTAMP_TIMEOUT, |state| state.time.is_none())
+            .unwrap();
+        Ok(state.time)
+    }
+
+    ///Removes offer `number` of `selection` unless it was replaced by a newer one.
+    fn withdraw(&self, selection: Atom, number: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .offers
+            .get(&selection)
+            .is_some_and(|(offered, _)| *offered == number)
+        {
+            state.offers.remove(&selection);
+        }
+    }
+
+    ///Answers requests of other clients until connection is lost.
+    fn serve(&self) -> Result<(), ReplyOrIdError> {
+        let mut transfers = HashMap::new();
+        loop {
+            match self.connection.wait_for_event()? {
+                Event::SelectionRequest(request) => self.answer(request, &mut transfers)?,
+                Event::PropertyNotify(event) if event.window == self.window => {
+                    self.state.lock().unwrap().time = Some(event.time);
+                    self.stamped.notify_all();
+                }
+                Event::PropertyNotify(event) if event.state == Property::DELETE => {
+                    self.resume(event, &mut transfers)?
+                }
+                Event::SelectionClear(event) => self.release(event.selection)?,
+                // requestor gone mid-transfer never deletes the property again
+                Event::DestroyNotify(event) => {
+                    transfers.retain(|(requestor, _), _| *requestor != event.window)
+                }
+                Event::Error(error) if error.error_kind == ErrorKind::Window => {
+                    transfers.retain(|(requestor, _), _| *requestor != error.bad_value)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    ///Forgets offer of `selection` somebody else took.
+    fn release(&self, selection: Atom) -> Result<(), ReplyOrIdError> {
+        // ownership may have been taken back by a newer offer already
+        if self
+            .connection
+            .get_selection_owner(selection)?
+            .reply()?
+            .owner
+            != self.window
+        {
+            self.state.lock().unwrap().offers.remove(&selection);
+            self.replaced.notify_all();
+        }
+        Ok(())
+    }
+
+    fn answer(
+        &self,
+        request: SelectionRequestEvent,
+        transfers: &mut HashMap<(Window, Atom), Transfer>,
+    ) -> Result<(), ReplyOrIdError> {
+        // obsolete clients don't name the property
+        let property = if request.property == NONE {
+            request.target
+        } else {
+            request.property
+        };
+        let served = match self.lookup(request.selection, request.target) {
+            Some(Answer::Targets(targets)) => {
+                self.connection.change_property32(
+                    PropMode::REPLACE,
+                    request.requestor,
+                    property,
+                    AtomEnum::ATOM,
+                    &targets,
+                )?;
+                true
+            }
+            Some(Answer::Content(content)) if content.len() <= INCR_CHUNK_SIZE => {
+                self.connection.change_property8(
+                    PropMode::REPLACE,
+                    request.requestor,
+                    property,
+                    request.target,
+                    &content,
+                )?;
+                true
+            }
+            Some(Answer::Content(content)) => {
+                // requestor deletes the property every time it is ready for the next chunk,
+                // transfer is dropped once its window is destroyed
+                self.connection.change_window_attributes(
+                    request.requestor,
+                    &ChangeWindowAttributesAux::new()
+                        .event_mask(EventMask::PROPERTY_CHANGE | EventMask::STRUCTURE_NOTIFY),
+                )?;
+                self.connection.change_property32(
+                    PropMode::REPLACE,
+                    request.requestor,
+                    property,
+                    self.incr,
+                    &[content.len() as u32],
+                )?;
+                transfers.insert(
+                    (request.requestor, property),
+                    Transfer {
+                        target: request.target,
+                        content,
+                        sent: 0,
+                    },
+                );
+                true
+            }
+            None => false,
+        };
+
+        let notify = SelectionNotifyEvent {
+            response_type: SELECTION_NOTIFY_EVENT,
+            sequence: 0,
+            time: request.time,
+            requestor: request.requestor,
+            selection: request.selection,
+            target: request.target,
+            property: if served { property } else { NONE },
+        };
+        self.connection
+            .send_event(false, request.requestor, EventMask::NO_EVENT, notify)?;
+        self.connection.flush()?;
+        Ok(())
+    }
+
+    fn lookup(&self, selection: Atom, target: Atom) -> Option<Answer> {
+        let state = self.state.lock().unwrap();
+        let (_, offer) = state.offers.get(&selection)?;
+        if target == self.targets {
+            let mut targets: Vec<_> = offer.iter().map(|(target, _)| *target).collect();
+            targets.push(self.targets);
+            return Some(Answer::Targets(targets));
+        }
+        offer
+            .iter()
+            .find(|(offered, _)| *offered == target)
+            .map(|(_, content)| Answer::Content(content.clone()))
+    }
+
+    ///Sends next chunk of transfer, the empty one ends it.
+    fn resume(
+        &self,
+        event: PropertyNotifyEvent,
+        transfers: &mut HashMap<(Window, Atom), Transfer>,
+    ) -> Result<(), ReplyOrIdError> {
+        let key = (event.window, event.atom);
+        let Some(transfer) = transfers.get_mut(&key) else {
+            return Ok(());
+        };
+        let end = transfer.content.len().min(transfer.sent + INCR_CHUNK_SIZE);
+        let chunk = &transfer.content[transfer.sent..end];
+        self.connection.change_property8(
+            PropMode::REPLACE,
+            event.window,
+            event.atom,
+            transfer.target,
+            chunk,
+        )?;
+        self.connection.flush()?;
+        if chunk.is_empty() {
+            transfers.remove(&key);
+        } else {
+            transfer.sent = end;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use crate::message;
+use arboard::{Clipboard, Get, Set};
+use std::io;
+
+///Selection which content has changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Selection {
+    ///Regular clipboard (`Ctrl+C`/`Ctrl+V`).
+    Clipboard,
+    ///X11/Wayland PRIMARY selection (select text, middle-click paste), only exists on Linux.
+    Primary,
+}
+
+///Selections that should be watched and synchronized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SelectionMode {
+    #[default]
+    Clipboard,
+    Primary,
+    Both,
+}
+
+impl SelectionMode {
+    ///Returns whether `selection` is covered by this mode.
+    pub fn contains(self, selection: Selection) -> bool {
+        matches!(
+            (self, selection),
+            (SelectionMode::Both, _)
+                | (SelectionMode::Clipboard, Selection::Clipboard)
+                | (SelectionMode::Primary, Selection::Primary)
+        )
+    }
+}
+
+impl Selection {
+    ///Starts reading from this selection.
+    ///
+    ///Returns `None` for PRIMARY on platforms without it.
+    pub fn get(self, clipboard: &mut Clipboard) -> Option<Get<'_>> {
+        #[cfg(all(
+            unix,
+            not(any(
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "android",
+                target_os = "emscripten"
+            ))
+        ))]
+        {
+            use arboard::{GetExtLinux, LinuxClipboardKind};
+            let kind = match self {
+                Selection::Clipboard => LinuxClipboardKind::Clipboard,
+                Selection::Primary => LinuxClipboardKind::Primary,
+            };
+            Some(clipboard.get().clipboard(kind))
+        }
+        #[cfg(not(all(
+            unix,
+            not(any(
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "android",
+                target_os = "emscripten"
+            ))
+        )))]
+        match self {
+            Selection::Clipboard => Some(clipboard.get()),
+            Selection::Primary => None,
+        }
+    }
+
+    ///Starts writing into this selection.
+    ///
+    ///Returns `None` for PRIMARY on platforms without it.
+    pub fn set(self, clipboard: &mut Clipboard) -> Option<Set<'_>> {
+        #[cfg(all(
+            unix,
+            not(any(
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "android",
+                target_os = "emscripten"
+            ))
+        ))]
+        {
+            use arboard::{LinuxClipboardKind, SetExtLinux};
+            let kind = match self {
+                Selection::Clipboard => LinuxClipboardKind::Clipboard,
+                Selection::Primary => LinuxClipboardKind::Primary,
+            };
+            Some(clipboard.set().clipboard(kind))
+        }
+        #[cfg(not(all(
+            unix,
+            not(any(
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "android",
+                target_os = "emscripten"
+            ))
+        )))]
+        match self {
+            Selection::Clipboard => Some(clipboard.set()),
+            Selection::Primary => None,
+        }
+    }
+
+    ///Returns whether this selection can hold several representations of content written by
+    ///`offer`.
+    pub fn offers_targets(self) -> bool {
+        #[cfg(all(
+            unix,
+            not(any(
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "android",
+                target_os = "emscripten"
+            ))
+        ))]
+        {
+            true
+        }
+        #[cfg(target_os = "macos")]
+        {
+            self == Selection::Clipboard
+        }
+        #[cfg(not(any(
+            all(
+                unix,
+                not(any(
+                    target_os = "macos",
+                    target_os = "ios",
+                    target_os = "android",
+                    target_os = "emscripten"
+                ))
+            ),
+            target_os = "macos"
+        )))]
+        {
+            false
+        }
+    }
+
+    ///Places content into this selection, every target (MIME type or X11 atom name) with its own
+    ///data.
+    ///
+    ///On Linux content is served by this process, `wait` blocks until something else is copied.
+    pub fn offer(self, targets: Vec<(&'static str, Vec<u8>)>, wait: bool) -> io::Result<()> {
+        #[cfg(all(
+            unix,
+            not(any(
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "android",
+                target_os = "emscripten"
+            ))
+        ))]
+        {
+            super::linux::offer(self, targets, wait)
+        }
+        #[cfg(target_os = "macos")]
+        {
+            let _ = wait;
+            match self {
+                Selection::Clipboard => super::mac::offer(targets),
+                Selection::Primary => Err(io::Error::from(io::ErrorKind::Unsupported)),
+            }
+        }
+        #[cfg(not(any(
+            all(
+                unix,
+                not(any(
+                    target_os = "macos",
+                    target_os = "ios",
+                    target_os = "android",
+                    target_os = "emscripten"
+                ))
+            ),
+            target_os = "macos"
+        )))]
+        {
+            let _ = (targets, wait);
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+    }
+}
+
+impl From<Selection> for message::Selection {
+    fn from(selection: Selection) -> Self {
+        match selection {
+            Selection::Clipboard => message::Selection::Clipboard,
+            Selection::Primary => message::Selection::Primary,
+        }
+    }
+}
+
+impl From<message::Selection> for Selection {
+    fn from(selection: message::Selection) -> Self {
+        match selection {
+            message::Selection::Clipboard => Selection::Clipboard,
+            message::Selection::Primary => Selection::Primary,
+        }
+    }
+}
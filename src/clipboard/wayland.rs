@@ -0,0 +1,384 @@
+use crate::clipboard::common::{CallbackResult, ClipboardHandler, ClipboardType};
+use crate::clipboard::{Selection, SelectionMode};
+
+use std::io;
+use std::sync::mpsc::{self, SyncSender, Receiver, sync_channel};
+
+use rustix::event::{poll, PollFd, PollFlags, Timespec};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_registry, wl_seat};
+use wayland_client::{delegate_noop, event_created_child, Connection, Dispatch, EventQueue, QueueHandle};
+use wayland_protocols::ext::data_control::v1::client::{
+    ext_data_control_device_v1::{self, ExtDataControlDeviceV1},
+    ext_data_control_manager_v1::ExtDataControlManagerV1,
+    ext_data_control_offer_v1::{self, ExtDataControlOfferV1},
+};
+use wayland_protocols_wlr::data_control::v1::client::{
+    zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+    zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+    zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+};
+
+///Shutdown channel
+///
+///On drop requests shutdown to gracefully close clipboard listener as soon as possible.
+#[allow(dead_code)]
+pub struct Shutdown {
+    sender: SyncSender<()>,
+}
+
+impl Drop for Shutdown {
+    #[inline(always)]
+    fn drop(&mut self) {
+        let _ = self.sender.send(());
+    }
+}
+
+///Clipboard master.
+///
+///Tracks changes of clipboard and invokes corresponding callbacks.
+///
+///# Platform notes:
+///
+///- On `wayland` it binds `ext-data-control` (or `wlr-data-control` as fallback) device of
+///  the first seat, so compositor must support one of these protocols.
+pub struct Master<H> {
+    handler: H,
+    #[allow(dead_code)]
+    sender: SyncSender<()>,
+    recv: Receiver<()>
+}
+
+impl<H: ClipboardHandler> Master<H> {
+    #[inline(always)]
+    ///Creates new instance.
+    pub fn new(handler: H) -> io::Result<Self> {
+        let (sender, recv) = sync_channel(0);
+
+        Ok(Self {
+            handler,
+            sender,
+            recv,
+        })
+    }
+
+    #[inline(always)]
+    ///Creates shutdown channel.
+    #[allow(dead_code)]
+    pub fn shutdown_channel(&self) -> Shutdown {
+        Shutdown {
+            sender: self.sender.clone()
+        }
+    }
+
+    ///Starts Master by waiting for selection offers from compositor
+    pub fn run(&mut self) -> io::Result<()> {
+        let connection = Connection::connect_to_env().map_err(io::Error::other)?;
+        let (globals, mut queue) = registry_queue_init::<State>(&connection).map_err(io::Error::other)?;
+        let qh = queue.handle();
+
+        let seat: wl_seat::WlSeat = globals.bind(&qh, 1..=8, ()).map_err(io::Error::other)?;
+        let device = if let Ok(manager) = globals.bind::<ExtDataControlManagerV1, _, _>(&qh, 1..=1, ()) {
+            DataDevice::Ext(manager.get_data_device(&seat, &qh, ()))
+        } else {
+            let manager = globals.bind::<ZwlrDataControlManagerV1, _, _>(&qh, 1..=2, ()).map_err(|error| io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Compositor supports neither ext-data-control nor wlr-data-control: {}", error),
+            ))?;
+            DataDevice::Wlr(manager.get_data_device(&seat, &qh, ()))
+        };
+
+        let mut state = State::new(self.handler.selections());
+        //Compositor announces current selection right after device creation, it is not a change
+        queue.roundtrip(&mut state).map_err(io::Error::other)?;
+        state.changes.clear();
+
+        let mut result = Ok(());
+        'main: loop {
+            if let Err(error) = self.wait_events(&connection, &mut queue, &mut state) {
+                match self.handler.on_clipboard_error(error) {
+                    CallbackResult::Next => (),
+                    CallbackResult::Stop => break,
+                    CallbackResult::StopWithError(error) => {
+                        result = Err(error);
+                        break;
+                    }
+                }
+            }
+
+            for (selection, clipboard_type) in state.changes.drain(..) {
+                let callback_result = match selection {
+                    Selection::Clipboard => self.handler.on_clipboard_change(clipboard_type),
+                    Selection::Primary => self.handler.on_primary_change(clipboard_type),
+                };
+                match callback_result {
+                    CallbackResult::Next => (),
+                    CallbackResult::Stop => break 'main,
+                    CallbackResult::StopWithError(error) => {
+                        result = Err(error);
+                        break 'main;
+                    }
+                }
+            }
+
+            if state.finished {
+                result = Err(io::Error::other("Data control device has been invalidated"));
+                break;
+            }
+
+            match self.recv.try_recv() {
+                Ok(()) => break,
+                Err(mpsc::TryRecvError::Empty) => continue,
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            }
+        }
+
+        device.destroy();
+        connection.flush().map_err(io::Error::other)?;
+        result
+    }
+
+    ///Reads and dispatches compositor events, waiting no longer than handler's sleep interval.
+    fn wait_events(&self, connection: &Connection, queue: &mut EventQueue<State>, state: &mut State) -> io::Result<()> {
+        queue.dispatch_pending(state).map_err(io::Error::other)?;
+        connection.flush().map_err(io::Error::other)?;
+
+        if let Some(guard) = queue.prepare_read() {
+            let interval = self.handler.sleep_interval();
+            let timeout = Timespec {
+                tv_sec: interval.as_secs() as _,
+                tv_nsec: interval.subsec_nanos() as _,
+            };
+
+            let ready = {
+                let fd = guard.connection_fd();
+                let mut fds = [PollFd::new(&fd, PollFlags::IN | PollFlags::ERR)];
+                match poll(&mut fds, Some(&timeout)) {
+                    Ok(ready) => ready > 0,
+                    Err(rustix::io::Errno::INTR) => false,
+                    Err(error) => return Err(error.into()),
+                }
+            };
+
+            if ready {
+                guard.read().map_err(io::Error::other)?;
+            }
+        }
+
+        queue.dispatch_pending(state).map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+///Data control device bound through whichever protocol compositor supports.
+enum DataDevice {
+    Ext(ExtDataControlDeviceV1),
+    Wlr(ZwlrDataControlDeviceV1),
+}
+
+impl DataDevice {
+    fn destroy(&self) {
+        match self {
+            DataDevice::Ext(device) => device.destroy(),
+            DataDevice::Wlr(device) => device.destroy(),
+        }
+    }
+}
+
+///Offer of data control device bound through either protocol.
+#[derive(PartialEq)]
+enum DataOffer {
+    Ext(ExtDataControlOfferV1),
+    Wlr(ZwlrDataControlOfferV1),
+}
+
+impl DataOffer {
+    fn destroy(&self) {
+        match self {
+            DataOffer::Ext(offer) => offer.destroy(),
+            DataOffer::Wlr(offer) => offer.destroy(),
+        }
+    }
+}
+
+///Dispatch state, collects MIME types of the offer announced last.
+///
+///Compositor announces offer right before selection event naming it, so an offer replaced by the
+///next one or not named by selection is never used. Offer type is generic for tests only.
+struct State<O = DataOffer> {
+    selections: SelectionMode,
+    offer: Option<(O, Vec<String>)>,
+    changes: Vec<(Selection, ClipboardType)>,
+    finished: bool,
+}
+
+impl<O: PartialEq> State<O> {
+    fn new(selections: SelectionMode) -> Self {
+        Self {
+            selections,
+            offer: None,
+            changes: Vec::new(),
+            finished: false,
+        }
+    }
+
+    ///Starts collecting MIME types of `offer`, returns the previous one that never became
+    ///selection.
+    fn on_offer(&mut self, offer: O) -> Option<O> {
+        self.offer.replace((offer, Vec::new())).map(|(offer, _)| offer)
+    }
+
+    fn on_mime_type(&mut self, offer: &O, mime_type: String) {
+        if let Some((_, mime_types)) = self.offer.as_mut().filter(|(announced, _)| announced == offer) {
+            mime_types.push(mime_type);
+        }
+    }
+
+    ///Takes MIME types of offer that became selection, `None` means selection has been cleared.
+    ///
+    ///Returns offer announced before that is not the selection.
+    fn on_selection(&mut self, selection: Selection, offer: Option<&O>) -> Option<O> {
+        let (announced, mime_types) = self.offer.take()?;
+        if offer != Some(&announced) {
+            return Some(announced);
+        }
+        if self.selections.contains(selection) {
+            self.changes.push((selection, ClipboardType::from_targets(mime_types.iter().map(String::as_str))));
+        }
+        None
+    }
+}
+
+impl State {
+    fn offer(&mut self, offer: DataOffer) {
+        if let Some(unused) = self.on_offer(offer) {
+            unused.destroy();
+        }
+    }
+
+    ///Destroys offer of `selection` once its MIME types are taken, as well as any unused one.
+    fn selection(&mut self, selection: Selection, offer: Option<DataOffer>) {
+        if let Some(unused) = self.on_selection(selection, offer.as_ref()) {
+            unused.destroy();
+        }
+        if let Some(offer) = offer {
+            offer.destroy();
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(_: &mut Self, _: &wl_registry::WlRegistry, _: wl_registry::Event, _: &GlobalListContents, _: &Connection, _: &QueueHandle<Self>) {}
+}
+
+delegate_noop!(State: ignore wl_seat::WlSeat);
+delegate_noop!(State: ExtDataControlManagerV1);
+delegate_noop!(State: ZwlrDataControlManagerV1);
+
+impl Dispatch<ExtDataControlDeviceV1, ()> for State {
+    fn event(state: &mut Self, _: &ExtDataControlDeviceV1, event: ext_data_control_device_v1::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {
+        match event {
+            ext_data_control_device_v1::Event::DataOffer { id } => state.offer(DataOffer::Ext(id)),
+            ext_data_control_device_v1::Event::Selection { id } => state.selection(Selection::Clipboard, id.map(DataOffer::Ext)),
+            ext_data_control_device_v1::Event::PrimarySelection { id } => state.selection(Selection::Primary, id.map(DataOffer::Ext)),
+            ext_data_control_device_v1::Event::Finished => state.finished = true,
+            _ => {}
+        }
+    }
+
+    event_created_child!(State, ExtDataControlDeviceV1, [
+        ext_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ExtDataControlOfferV1, ()),
+    ]);
+}
+
+impl Dispatch<ExtDataControlOfferV1, ()> for State {
+    fn event(state: &mut Self, offer: &ExtDataControlOfferV1, event: ext_data_control_offer_v1::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {
+        if let ext_data_control_offer_v1::Event::Offer { mime_type } = event {
+            state.on_mime_type(&DataOffer::Ext(offer.clone()), mime_type);
+        }
+    }
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for State {
+    fn event(state: &mut Self, _: &ZwlrDataControlDeviceV1, event: zwlr_data_control_device_v1::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {
+        match event {
+            zwlr_data_control_device_v1::Event::DataOffer { id } => state.offer(DataOffer::Wlr(id)),
+            zwlr_data_control_device_v1::Event::Selection { id } => state.selection(Selection::Clipboard, id.map(DataOffer::Wlr)),
+            zwlr_data_control_device_v1::Event::PrimarySelection { id } => state.selection(Selection::Primary, id.map(DataOffer::Wlr)),
+            zwlr_data_control_device_v1::Event::Finished => state.finished = true,
+            _ => {}
+        }
+    }
+
+    event_created_child!(State, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrDataControlOfferV1, ()> for State {
+    fn event(state: &mut Self, offer: &ZwlrDataControlOfferV1, event: zwlr_data_control_offer_v1::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {
+        if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
+            state.on_mime_type(&DataOffer::Wlr(offer.clone()), mime_type);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///Announces offer `id` with `mime_types`, returns unused offer it replaced.
+    fn announce(state: &mut State<u32>, id: u32, mime_types: &[&str]) -> Option<u32> {
+        let unused = state.on_offer(id);
+        for mime_type in mime_types {
+            state.on_mime_type(&id, mime_type.to_string());
+        }
+        unused
+    }
+
+    #[test]
+    fn selected_offer_is_reported_with_its_type() {
+        let mut state = State::new(SelectionMode::Clipboard);
+        assert_eq!(announce(&mut state, 1, &["text/plain", "image/png"]), None);
+        assert_eq!(state.on_selection(Selection::Clipboard, Some(&1)), None);
+
+        assert!(matches!(state.changes[..], [(Selection::Clipboard, ClipboardType::IMAGE)]));
+        assert!(state.offer.is_none());
+    }
+
+    #[test]
+    fn offer_replaced_by_next_one_is_dropped() {
+        let mut state = State::new(SelectionMode::Clipboard);
+        announce(&mut state, 1, &["text/uri-list"]);
+        assert_eq!(announce(&mut state, 2, &["text/html"]), Some(1));
+        state.on_mime_type(&1, "image/png".to_owned());
+        assert_eq!(state.on_selection(Selection::Clipboard, Some(&2)), None);
+
+        assert!(matches!(state.changes[..], [(Selection::Clipboard, ClipboardType::HTML)]));
+        assert!(state.offer.is_none());
+    }
+
+    #[test]
+    fn offer_not_named_by_selection_is_dropped() {
+        let mut state = State::new(SelectionMode::Both);
+        announce(&mut state, 1, &["text/plain"]);
+        assert_eq!(state.on_selection(Selection::Clipboard, None), Some(1));
+        announce(&mut state, 2, &["text/plain"]);
+        assert_eq!(state.on_selection(Selection::Primary, Some(&3)), Some(2));
+        assert_eq!(state.on_selection(Selection::Primary, Some(&2)), None);
+
+        assert!(state.changes.is_empty());
+        assert!(state.offer.is_none());
+    }
+
+    #[test]
+    fn selection_outside_mode_is_not_reported() {
+        let mut state = State::new(SelectionMode::Clipboard);
+        announce(&mut state, 1, &["text/plain"]);
+        assert_eq!(state.on_selection(Selection::Primary, Some(&1)), None);
+        announce(&mut state, 2, &["text/plain"]);
+        assert_eq!(state.on_selection(Selection::Clipboard, Some(&2)), None);
+
+        assert!(matches!(state.changes[..], [(Selection::Clipboard, ClipboardType::TEXT)]));
+    }
+}
@@ -21,6 +21,7 @@ const CLOSE_PARAM: isize = -1;
 ///Shutdown channel
 ///
 ///On drop requests shutdown to gracefully close clipboard listener as soon as possible.
+#[allow(dead_code)]
 pub struct Shutdown {
     window: HWND,
 }
@@ -93,6 +94,7 @@ impl<H: ClipboardHandler> Master<H> {
 
     #[inline(always)]
     ///Creates shutdown channel.
+    #[allow(dead_code)]
     pub fn shutdown_channel(&self) -> Shutdown {
         Shutdown {
             window: self.window.inner()
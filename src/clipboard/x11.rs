@@ -1,16 +1,22 @@
-use crate::{CallbackResult, ClipboardHandler};
+use crate::clipboard::common::{CallbackResult, ClipboardHandler, ClipboardType};
+use crate::clipboard::Selection;
 
 use std::io;
 use std::sync::OnceLock;
 use std::sync::mpsc::{self, SyncSender, Receiver, sync_channel};
+use std::time::Duration;
 
-use x11rb::protocol::xfixes;
+use x11rb::protocol::{xfixes, Event};
 use x11rb::connection::Connection;
-use x11rb::protocol::xproto::ConnectionExt;
+use x11rb::protocol::xproto::{Atom, ConnectionExt};
+
+///Time to wait for selection owner to answer TARGETS request.
+const TARGETS_TIMEOUT: Duration = Duration::from_millis(500);
 
 ///Shutdown channel
 ///
 ///On drop requests shutdown to gracefully close clipboard listener as soon as possible.
+#[allow(dead_code)]
 pub struct Shutdown {
     sender: SyncSender<()>,
 }
@@ -31,6 +37,7 @@ impl Drop for Shutdown {
 ///- On `windows` it creates dummy window that monitors each clipboard change message.
 pub struct Master<H> {
     handler: H,
+    #[allow(dead_code)]
     sender: SyncSender<()>,
     recv: Receiver<()>
 }
@@ -50,6 +57,7 @@ impl<H: ClipboardHandler> Master<H> {
 
     #[inline(always)]
     ///Creates shutdown channel.
+    #[allow(dead_code)]
     pub fn shutdown_channel(&self) -> Shutdown {
         Shutdown {
             sender: self.sender.clone()
@@ -76,7 +84,11 @@ impl<H: ClipboardHandler> Master<H> {
 
         let mut result = Ok(());
         'main: loop {
-            let selection = clipboard.getter.atoms.clipboard;
+            let selections = self.handler.selections();
+            let selections = [
+                (Selection::Clipboard, clipboard.getter.atoms.clipboard),
+                (Selection::Primary, clipboard.getter.atoms.primary),
+            ].into_iter().filter(|(selection, _)| selections.contains(*selection)).collect::<Vec<_>>();
 
             let screen = match clipboard.getter.connection.setup().roots.get(clipboard.getter.screen) {
                 Some(screen) => screen,
@@ -101,13 +113,14 @@ impl<H: ClipboardHandler> Master<H> {
                 screen.root,
                 clipboard.getter.atoms.clipboard,
                 xfixes::SelectionEventMask::default()
-            // ...and set the one requested now
-            )).and_then(|_| xfixes::select_selection_input(
+            ));
+            // ...and set the ones requested now
+            let cookie = selections.iter().fold(cookie, |cookie, (_, atom)| cookie.and_then(|_| xfixes::select_selection_input(
                 &clipboard.getter.connection,
                 screen.root,
-                selection,
+                *atom,
                 xfixes::SelectionEventMask::SET_SELECTION_OWNER | xfixes::SelectionEventMask::SELECTION_CLIENT_CLOSE | xfixes::SelectionEventMask::SELECTION_WINDOW_DESTROY
-            ));
+            )));
 
             if let Err(error) = clipboard.getter.connection.flush() {
                 match self.handler.on_clipboard_error(io::Error::new(io::ErrorKind::Other, error)) {
@@ -147,8 +160,13 @@ impl<H: ClipboardHandler> Master<H> {
 
             'poll: loop {
                 match clipboard.getter.connection.poll_for_event_with_sequence() {
-                    Ok(Some((_, seq))) if seq >= sequence_number => {
-                        match self.handler.on_clipboard_change() {
+                    Ok(Some((Event::XfixesSelectionNotify(event), seq))) if seq >= sequence_number => {
+                        let clipboard_type = Self::clipboard_type(event.selection);
+                        let callback_result = match selections.iter().find(|(_, atom)| *atom == event.selection) {
+                            Some((Selection::Primary, _)) => self.handler.on_primary_change(clipboard_type),
+                            _ => self.handler.on_clipboard_change(clipboard_type),
+                        };
+                        match callback_result {
                             CallbackResult::Next => break 'poll,
                             CallbackResult::Stop => break 'main,
                             CallbackResult::StopWithError(error) => {
@@ -157,7 +175,9 @@ impl<H: ClipboardHandler> Master<H> {
                             }
                         }
                     },
-                    Ok(_) => {
+                    //Unrelated or stale event, drain it without waiting
+                    Ok(Some(_)) => continue 'poll,
+                    Ok(None) => {
                         match self.recv.recv_timeout(self.handler.sleep_interval()) {
                             Ok(()) => break 'main,
                             //timeout
@@ -214,6 +234,32 @@ impl<H: ClipboardHandler> Master<H> {
         }
     }
 
+    ///Asks owner of `selection` for its TARGETS and maps them into `ClipboardType`.
+    ///
+    ///Separate connection is used, so events of the watching one are never consumed while waiting.
+    fn clipboard_type(selection: Atom) -> ClipboardType {
+        let clipboard = match targets_clipboard() {
+            Ok(clipboard) => clipboard,
+            Err(_) => return ClipboardType::UNKNOWN,
+        };
+        let targets = match clipboard.load(selection, clipboard.getter.atoms.targets, clipboard.getter.atoms.property, TARGETS_TIMEOUT) {
+            Ok(targets) => targets,
+            Err(_) => return ClipboardType::UNKNOWN,
+        };
+
+        //TARGETS is a list of 32-bit atoms, request all names first to avoid round trip per atom
+        let cookies = targets.chunks_exact(4)
+                             .map(|atom| u32::from_ne_bytes([atom[0], atom[1], atom[2], atom[3]]))
+                             .filter_map(|atom| clipboard.getter.connection.get_atom_name(atom).ok())
+                             .collect::<Vec<_>>();
+        let names = cookies.into_iter()
+                           .filter_map(|cookie| cookie.reply().ok())
+                           .map(|reply| String::from_utf8_lossy(&reply.name).into_owned())
+                           .collect::<Vec<_>>();
+
+        ClipboardType::from_targets(names.iter().map(String::as_str))
+    }
+
     ///Gets one time initialized x11 clipboard.
     ///
     ///This is only available on linux
@@ -221,7 +267,17 @@ impl<H: ClipboardHandler> Master<H> {
     ///Prefer to use it on Linux as underlying `x11-clipboard` crate has buggy dtor
     ///and doesn't clean up all resources associated with `Clipboard`
     pub fn x11_clipboard() -> &'static Result<x11_clipboard::Clipboard, x11_clipboard::error::Error> {
-        static CLIP: OnceLock<Result<x11_clipboard::Clipboard, x11_clipboard::error::Error>> = OnceLock::new();
-        CLIP.get_or_init(x11_clipboard::Clipboard::new)
+        x11_clipboard()
     }
 }
+
+fn x11_clipboard() -> &'static Result<x11_clipboard::Clipboard, x11_clipboard::error::Error> {
+    static CLIP: OnceLock<Result<x11_clipboard::Clipboard, x11_clipboard::error::Error>> = OnceLock::new();
+    CLIP.get_or_init(x11_clipboard::Clipboard::new)
+}
+
+///Clipboard asking owners for their TARGETS.
+fn targets_clipboard() -> &'static Result<x11_clipboard::Clipboard, x11_clipboard::error::Error> {
+    static CLIP: OnceLock<Result<x11_clipboard::Clipboard, x11_clipboard::error::Error>> = OnceLock::new();
+    CLIP.get_or_init(x11_clipboard::Clipboard::new)
+}
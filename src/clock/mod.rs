@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+mod clock;
+
+pub use clock::now;
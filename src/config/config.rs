@@ -0,0 +1,62 @@
+use crate::auth;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tonic::Status;
+
+/// Server settings read from `--config` file:
+///
+/// ```toml
+/// [channels.default]
+///
+/// [channels.design]
+/// tokens = ["design-secret"]
+/// ```
+///
+/// Only listed channels may be joined, channel without `tokens` is open to anyone passing
+/// `--token` check. Tokens of a channel are checked when peer joins it, peer presents a single
+/// token, so with `--token` set they must include it. Without config every channel is allowed.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    #[serde(default)]
+    channels: HashMap<String, Channel>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Channel {
+    #[serde(default)]
+    tokens: Vec<String>,
+}
+
+impl ServerConfig {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = std::fs::read_to_string(path)?;
+        toml::from_str(&content)
+            .map_err(|e| format!("invalid config {}: {}", path.display(), e).into())
+    }
+
+    /// Checks whether peer presenting `token` may join `channel`.
+    #[allow(clippy::result_large_err)]
+    pub fn admit(&self, channel: &str, token: Option<&str>) -> Result<(), Status> {
+        if self.channels.is_empty() {
+            return Ok(());
+        }
+
+        let Some(config) = self.channels.get(channel) else {
+            return Err(Status::permission_denied(format!(
+                "unknown channel: {}",
+                channel
+            )));
+        };
+        if config.tokens.is_empty() || token.is_some_and(|t| auth::is_one_of(t, &config.tokens)) {
+            Ok(())
+        } else {
+            Err(Status::permission_denied(format!(
+                "not allowed to join channel: {}",
+                channel
+            )))
+        }
+    }
+}
@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+mod config;
+
+pub use config::ServerConfig;
@@ -0,0 +1,204 @@
+use crate::message::{self, message::Payload as Body, ContentKind, Message};
+use crate::payload::PayloadError;
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload as AeadPayload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use std::sync::Arc;
+
+/// Salt of key derivation, fixed so that every peer derives the same key from the passphrase.
+const SALT: &[u8] = b"lanclip/sealed/1";
+
+/// End-to-end encryption of message payloads.
+///
+/// Key is derived from the shared passphrase once at start, messages carry the salt only to
+/// tell which derivation they were sealed with, so receiving them never costs a derivation.
+/// Disabled cipher passes messages through untouched.
+#[derive(Clone, Default)]
+pub struct Cipher {
+    key: Option<Arc<XChaCha20Poly1305>>,
+}
+
+impl Cipher {
+    pub fn new(passphrase: Option<String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let Some(passphrase) = passphrase else {
+            return Ok(Self::default());
+        };
+        if passphrase.is_empty() {
+            return Err("passphrase must not be empty".into());
+        }
+
+        let key = derive(&passphrase, SALT).ok_or("failed to derive key")?;
+        Ok(Self {
+            key: Some(Arc::new(key)),
+        })
+    }
+
+    /// Encrypts payload of `message`, selection, version, origin and sequence stay readable but
+    /// authenticated, so `message` must be stamped already.
+    pub fn seal(&self, mut message: Message) -> Result<Message, PayloadError> {
+        let Some(key) = &self.key else {
+            return Ok(message);
+        };
+        let Some(kind) = message.payload.as_ref().and_then(kind) else {
+            return Ok(message);
+        };
+        // hash of short text is easily reversed
+        message.content_hash.clear();
+
+        let plain = prost::Message::encode_to_vec(&Message {
+            payload: message.payload.take(),
+            ..Default::default()
+        });
+        let aad = aad(&message, kind);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = key
+            .encrypt(
+                &nonce,
+                AeadPayload {
+                    msg: &plain,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| PayloadError::TooLarge(plain.len() as u64))?;
+
+        message.payload = Some(Body::Sealed(message::Sealed {
+            salt: SALT.to_vec(),
+            nonce: nonce.to_vec(),
+            ciphertext,
+            kind: kind.into(),
+        }));
+        Ok(message)
+    }
+
+    /// Decrypts payload of `message`, plaintext messages are refused once passphrase is set.
+    pub fn open(&self, mut message: Message) -> Result<Message, PayloadError> {
+        let Some(key) = &self.key else {
+            return Ok(message);
+        };
+        let sealed = match message.payload.take() {
+            Some(Body::Sealed(sealed)) => sealed,
+            Some(_) => return Err(PayloadError::Unsealed),
+            None => return Err(PayloadError::Empty),
+        };
+        if sealed.salt != SALT || sealed.nonce.len() != XNonce::default().len() {
+            return Err(PayloadError::Tampered);
+        }
+
+        let plain = key
+            .decrypt(
+                XNonce::from_slice(&sealed.nonce),
+                AeadPayload {
+                    msg: &sealed.ciphertext,
+                    aad: &aad(&message, sealed.kind()),
+                },
+            )
+            .map_err(|_| PayloadError::Tampered)?;
+        let inner: Message =
+            prost::Message::decode(plain.as_slice()).map_err(|_| PayloadError::Tampered)?;
+        if inner.payload.as_ref().and_then(kind) != Some(sealed.kind()) {
+            return Err(PayloadError::Tampered);
+        }
+
+        message.payload = inner.payload;
+        Ok(message)
+    }
+}
+
+fn derive(passphrase: &str, salt: &[u8]) -> Option<XChaCha20Poly1305> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .ok()?;
+    Some(XChaCha20Poly1305::new(&key))
+}
+
+fn kind(body: &Body) -> Option<ContentKind> {
+    match body {
+        Body::Text(_) => Some(ContentKind::Text),
+        Body::Image(_) => Some(ContentKind::Image),
+        Body::Files(_) => Some(ContentKind::Files),
+        Body::Html(_) => Some(ContentKind::Html),
+        Body::Rtf(_) => Some(ContentKind::Rtf),
+        Body::Compressed(compressed) => Some(compressed.kind()),
+        Body::Item(_) => Some(ContentKind::Item),
+        Body::Sealed(_) | Body::Chunk(_) => None,
+    }
+}
+
+/// Cleartext fields bound to ciphertext, so hub can't move content to other selection or pass
+/// old content off as a new change of any node.
+fn aad(message: &Message, kind: ContentKind) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(28 + message.origin.len());
+    aad.extend_from_slice(&message.selection.to_le_bytes());
+    aad.extend_from_slice(&message.version.to_le_bytes());
+    aad.extend_from_slice(&(kind as i32).to_le_bytes());
+    aad.extend_from_slice(&message.sequence.to_le_bytes());
+    aad.extend_from_slice(&(message.origin.len() as u64).to_le_bytes());
+    aad.extend_from_slice(message.origin.as_bytes());
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Text;
+
+    fn text() -> Message {
+        Message {
+            payload: Some(Body::Text(Text {
+                text: "secret".to_owned(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn sealed(message: &mut Message) -> &mut message::Sealed {
+        match &mut message.payload {
+            Some(Body::Sealed(sealed)) => sealed,
+            other => panic!("message is not sealed: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sealed_message_is_opened_only_with_same_passphrase() {
+        let cipher = Cipher::new(Some("correct-horse".to_owned())).unwrap();
+        let message = cipher.seal(text()).unwrap();
+        assert_eq!(cipher.open(message.clone()).unwrap(), text());
+
+        let other = Cipher::new(Some("battery-staple".to_owned())).unwrap();
+        assert!(matches!(other.open(message), Err(PayloadError::Tampered)));
+        assert!(matches!(cipher.open(text()), Err(PayloadError::Unsealed)));
+    }
+
+    #[test]
+    fn unknown_salt_is_refused() {
+        let cipher = Cipher::new(Some("correct-horse".to_owned())).unwrap();
+        let mut message = cipher.seal(text()).unwrap();
+        sealed(&mut message).salt = vec![7; 16];
+        assert!(matches!(cipher.open(message), Err(PayloadError::Tampered)));
+    }
+
+    #[test]
+    fn origin_and_sequence_are_authenticated() {
+        let cipher = Cipher::new(Some("correct-horse".to_owned())).unwrap();
+        let message = cipher
+            .seal(Message {
+                origin: "node".to_owned(),
+                sequence: 7,
+                ..text()
+            })
+            .unwrap();
+        assert!(cipher.open(message.clone()).is_ok());
+
+        let replayed = Message {
+            sequence: 8,
+            ..message.clone()
+        };
+        assert!(matches!(cipher.open(replayed), Err(PayloadError::Tampered)));
+        let moved = Message {
+            origin: "other".to_owned(),
+            ..message
+        };
+        assert!(matches!(cipher.open(moved), Err(PayloadError::Tampered)));
+    }
+}
@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+mod crypto;
+
+pub use crypto::Cipher;
@@ -0,0 +1,161 @@
+use crate::message::{Announcement, Probe};
+use prost::Message;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use uuid::Uuid;
+
+/// UDP port servers listen on for probes.
+pub const DISCOVERY_PORT: u16 = 9982;
+
+const SERVICE: &str = "lanclip";
+
+/// Answers discovery probes with description of the running server.
+pub struct Advertiser {
+    announcement: Announcement,
+}
+
+/// Server that answered a probe.
+#[derive(Debug, Clone)]
+pub struct Server {
+    pub addr: SocketAddr,
+    pub announcement: Announcement,
+}
+
+impl Advertiser {
+    pub fn new(port: u16, relay: bool) -> Self {
+        Self {
+            announcement: Announcement {
+                service: SERVICE.to_owned(),
+                port: port as u32,
+                instance_id: Uuid::new_v4().to_string(),
+                hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+                relay,
+                peer: false,
+            },
+        }
+    }
+
+    /// Advertises mesh node, `instance_id` lets other nodes recognize it.
+    pub fn peer(port: u16, instance_id: String) -> Self {
+        let mut advertiser = Self::new(port, false);
+        advertiser.announcement.instance_id = instance_id;
+        advertiser.announcement.peer = true;
+        advertiser
+    }
+
+    pub async fn run(self, discovery_port: u16) -> io::Result<()> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, discovery_port)).await?;
+        println!("answering discovery probes on udp port: {}", discovery_port);
+        self.answer(socket).await
+    }
+
+    /// Answers probes received on `socket`, failing to answer one of them doesn't stop it.
+    async fn answer(self, socket: UdpSocket) -> io::Result<()> {
+        let reply = self.announcement.encode_to_vec();
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await?;
+            match Probe::decode(&buf[..len]) {
+                Ok(probe) if probe.service == SERVICE => {
+                    if let Err(e) = socket.send_to(&reply, from).await {
+                        println!("failed to answer probe from {}: {}", from, e);
+                    }
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// Probes local network and collects servers answering within `timeout`.
+///
+/// Probe is broadcast and also sent to loopback, so server on the same machine is found too.
+pub async fn discover(discovery_port: u16, timeout: Duration) -> io::Result<Vec<Server>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_broadcast(true)?;
+
+    let probe = Probe {
+        service: SERVICE.to_owned(),
+    }
+    .encode_to_vec();
+    for target in [Ipv4Addr::BROADCAST, Ipv4Addr::LOCALHOST] {
+        if let Err(e) = socket.send_to(&probe, (target, discovery_port)).await {
+            println!("failed to send probe to {}: {}", target, e);
+        }
+    }
+
+    let mut servers: Vec<Server> = vec![];
+    let mut buf = [0u8; 512];
+    let deadline = tokio::time::Instant::now() + timeout;
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, from) = received?;
+        let Ok(announcement) = Announcement::decode(&buf[..len]) else {
+            continue;
+        };
+        if announcement.service != SERVICE
+            || servers
+                .iter()
+                .any(|s| s.announcement.instance_id == announcement.instance_id)
+        {
+            continue;
+        }
+        servers.push(Server {
+            addr: SocketAddr::new(from.ip(), announcement.port as u16),
+            announcement,
+        });
+    }
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Advertiser answering on loopback, returns its discovery port.
+    async fn advertise(advertiser: Advertiser) -> u16 {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(advertiser.answer(socket));
+        port
+    }
+
+    #[tokio::test]
+    async fn probe_is_answered() {
+        let advertiser = Advertiser::peer(9981, "node".to_owned());
+        let port = advertise(advertiser).await;
+
+        let servers = discover(port, Duration::from_millis(500)).await.unwrap();
+        assert_eq!(servers.len(), 1);
+        let server = &servers[0];
+        assert_eq!(server.addr, SocketAddr::from((Ipv4Addr::LOCALHOST, 9981)));
+        assert_eq!(server.announcement.instance_id, "node");
+        assert!(server.announcement.peer);
+        assert!(!server.announcement.relay);
+    }
+
+    #[tokio::test]
+    async fn foreign_datagrams_are_ignored() {
+        let port = advertise(Advertiser::new(9981, true)).await;
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let other = Probe {
+            service: "other".to_owned(),
+        };
+        for datagram in [b"garbage".to_vec(), other.encode_to_vec()] {
+            socket
+                .send_to(&datagram, (Ipv4Addr::LOCALHOST, port))
+                .await
+                .unwrap();
+        }
+        let mut buf = [0u8; 512];
+        let reply = tokio::time::timeout(Duration::from_millis(200), socket.recv_from(&mut buf));
+        assert!(reply.await.is_err());
+
+        // advertiser keeps answering
+        let servers = discover(port, Duration::from_millis(500)).await.unwrap();
+        assert_eq!(servers.len(), 1);
+        assert!(servers[0].announcement.relay);
+    }
+}
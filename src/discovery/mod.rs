@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+mod discovery;
+
+pub use discovery::{discover, Advertiser, DISCOVERY_PORT};
@@ -0,0 +1,152 @@
+use crate::clipboard::Selection;
+use crate::message::Message;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// Identity of this node, stamped on every message it produces.
+#[derive(Clone)]
+pub struct Origin {
+    id: Arc<str>,
+    sequence: Arc<AtomicU64>,
+}
+
+impl Default for Origin {
+    fn default() -> Self {
+        Self {
+            id: Uuid::new_v4().to_string().into(),
+            sequence: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Origin {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Marks `message` as the next one produced by this node.
+    pub fn stamp(&self, mut message: Message) -> Message {
+        message.origin = self.id.to_string();
+        message.sequence = self.sequence.fetch_add(1, Ordering::SeqCst) + 1;
+        message
+    }
+}
+
+/// Content every selection is known to share with the peers, by its hash.
+///
+/// Writing received content changes local clipboard too. That change is recognized by its hash
+/// and attributed to the node content came from, however late or how many times it is reported.
+#[derive(Clone, Default)]
+pub struct Synced {
+    current: Arc<Mutex<HashMap<Selection, Current>>>,
+}
+
+struct Current {
+    hash: Vec<u8>,
+    origin: String,
+    /// Local clipboard already reported the change.
+    seen: bool,
+}
+
+impl Synced {
+    /// Returns whether `selection` already holds content of `hash`.
+    pub fn is_current(&self, selection: Selection, hash: &[u8]) -> bool {
+        self.current
+            .lock()
+            .unwrap()
+            .get(&selection)
+            .is_some_and(|current| current.hash == hash)
+    }
+
+    /// Records content of `hash` from `origin` that is about to be written into `selection`.
+    ///
+    /// Returns `false` if `selection` already holds it, then there is nothing to write.
+    pub fn received(&self, selection: Selection, hash: Vec<u8>, origin: &str) -> bool {
+        let mut current = self.current.lock().unwrap();
+        if current.get(&selection).is_some_and(|c| c.hash == hash) {
+            return false;
+        }
+        current.insert(
+            selection,
+            Current {
+                hash,
+                origin: origin.to_owned(),
+                seen: false,
+            },
+        );
+        true
+    }
+
+    /// Records change of `selection` to content of `hash` reported by local clipboard.
+    ///
+    /// Returns node the content came from, `local` if it was copied on this machine, or `None`
+    /// if the change was already reported.
+    pub fn changed(&self, selection: Selection, hash: Vec<u8>, local: &str) -> Option<String> {
+        let mut current = self.current.lock().unwrap();
+        match current.get_mut(&selection) {
+            Some(current) if current.hash == hash => {
+                if std::mem::replace(&mut current.seen, true) {
+                    None
+                } else {
+                    Some(current.origin.clone())
+                }
+            }
+            _ => {
+                current.insert(
+                    selection,
+                    Current {
+                        hash,
+                        origin: local.to_owned(),
+                        seen: true,
+                    },
+                );
+                Some(local.to_owned())
+            }
+        }
+    }
+
+    /// Forgets content of `selection` that failed to be written.
+    pub fn forget(&self, selection: Selection) {
+        self.current.lock().unwrap().remove(&selection);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: &str = "local";
+
+    #[test]
+    fn written_content_is_attributed_to_its_origin_once() {
+        let synced = Synced::default();
+        assert!(synced.received(Selection::Clipboard, vec![1], "peer"));
+        assert!(!synced.received(Selection::Clipboard, vec![1], "other"));
+
+        let changed = synced.changed(Selection::Clipboard, vec![1], LOCAL);
+        assert_eq!(changed.as_deref(), Some("peer"));
+        assert_eq!(synced.changed(Selection::Clipboard, vec![1], LOCAL), None);
+    }
+
+    #[test]
+    fn copied_content_is_local() {
+        let synced = Synced::default();
+        synced.received(Selection::Clipboard, vec![1], "peer");
+
+        let changed = synced.changed(Selection::Clipboard, vec![2], LOCAL);
+        assert_eq!(changed.as_deref(), Some(LOCAL));
+        assert!(synced.is_current(Selection::Clipboard, &[2]));
+        assert!(!synced.is_current(Selection::Primary, &[2]));
+    }
+
+    #[test]
+    fn failed_write_is_forgotten() {
+        let synced = Synced::default();
+        synced.received(Selection::Clipboard, vec![1], "peer");
+        synced.forget(Selection::Clipboard);
+
+        assert!(synced.received(Selection::Clipboard, vec![1], "peer"));
+    }
+}
@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+mod echo;
+
+pub use echo::{Origin, Synced};
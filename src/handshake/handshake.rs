@@ -0,0 +1,342 @@
+use crate::message::{
+    self, message::Payload as Body, Compression, ContentKind, Hello, ImageEncoding, Message,
+};
+use crate::payload::{
+    compress, content_kind, decode_image, decompress, max_item_size, richest, Encoding,
+    COMPRESSIONS, IMAGE_ENCODINGS, VERSION,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::Status;
+use uuid::Uuid;
+
+/// Metadata key carrying session id from `Welcome` on `Changed` stream.
+pub const SESSION_HEADER: &str = "x-lanclip-session";
+
+/// Channel of peers that didn't ask for any.
+pub const DEFAULT_CHANNEL: &str = "default";
+
+/// How long session waits for its `Changed` stream.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sessions waiting for `Changed` stream at once, handshakes beyond it are refused.
+const MAX_PENDING: usize = 1024;
+
+/// Describes this build to the remote side.
+pub fn hello() -> Hello {
+    Hello {
+        protocol_version: VERSION,
+        hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+        os: std::env::consts::OS.to_owned(),
+        supported: vec![
+            ContentKind::Text.into(),
+            ContentKind::Image.into(),
+            ContentKind::Files.into(),
+            ContentKind::Html.into(),
+            ContentKind::Item.into(),
+        ],
+        max_payload_size: max_item_size() as u64,
+        channel: DEFAULT_CHANNEL.to_owned(),
+        image_encodings: IMAGE_ENCODINGS.map(Into::into).to_vec(),
+        compressions: COMPRESSIONS.map(Into::into).to_vec(),
+        last_revision: 0,
+        changed_offline: false,
+    }
+}
+
+/// Channel peer wants to join.
+pub fn channel(hello: &Hello) -> &str {
+    if hello.channel.is_empty() {
+        DEFAULT_CHANNEL
+    } else {
+        &hello.channel
+    }
+}
+
+/// What remote side is able to receive.
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    supported: Vec<ContentKind>,
+    max_payload_size: u64,
+    image_encodings: Vec<ImageEncoding>,
+    compressions: Vec<Compression>,
+}
+
+impl From<&Hello> for Capabilities {
+    fn from(hello: &Hello) -> Self {
+        Self {
+            supported: hello.supported().collect(),
+            max_payload_size: hello.max_payload_size,
+            image_encodings: hello.image_encodings().collect(),
+            compressions: hello.compressions().collect(),
+        }
+    }
+}
+
+impl Capabilities {
+    /// Returns whether message should be sent to the peer.
+    pub fn accepts(&self, message: &Message) -> bool {
+        let Some(kind) = content_kind(message) else {
+            return false;
+        };
+        self.supported.contains(&kind)
+            && prost::Message::encoded_len(message) as u64 <= self.max_payload_size
+    }
+
+    /// Encoding content should be sent to the peer with.
+    pub fn encoding(&self) -> Encoding {
+        let preferred = Encoding::preferred();
+        Encoding {
+            image: if self.image_encodings.contains(&preferred.image) {
+                preferred.image
+            } else {
+                ImageEncoding::Raw
+            },
+            compression: if self.compressions.contains(&preferred.compression) {
+                preferred.compression
+            } else {
+                Compression::None
+            },
+            html: self.supported.contains(&ContentKind::Html),
+            items: self.supported.contains(&ContentKind::Item),
+        }
+    }
+
+    /// Converts content the peer can't decode to plain form, encrypted one is left as is.
+    ///
+    /// Items are reduced to the richest representation the peer supports. Compressed content
+    /// that has to change is compressed again if the peer decodes the codec.
+    pub fn fit(&self, mut message: Message) -> Message {
+        if let Some(Body::Compressed(compressed)) = &message.payload {
+            let codec = compressed.codec();
+            let decoded = self.compressions.contains(&codec);
+            if decoded && !self.reduces(compressed.kind()) {
+                return message;
+            }
+            match decompress(compressed) {
+                Ok(inner) => message.payload = inner.payload,
+                Err(e) => {
+                    println!("failed to decompress message for peer: {}", e);
+                    return message;
+                }
+            }
+            if decoded {
+                return compress(self.fit(message), codec);
+            }
+        }
+
+        if let Some(Body::Item(item)) = &mut message.payload {
+            if self.supported.contains(&ContentKind::Item) {
+                for representation in &mut item.representations {
+                    *representation = self.fit(std::mem::take(representation));
+                }
+                return message;
+            }
+            message.payload = richest(std::mem::take(item), &self.supported);
+        }
+
+        if let Some(Body::Html(html)) = &mut message.payload {
+            if !self.supported.contains(&ContentKind::Html) {
+                let text = std::mem::take(&mut html.alt_text);
+                message.payload = Some(Body::Text(message::Text { text }));
+            }
+            return message;
+        }
+
+        let Some(Body::Image(image)) = &mut message.payload else {
+            return message;
+        };
+        let encoding = image.encoding();
+        if encoding == ImageEncoding::Raw || self.image_encodings.contains(&encoding) {
+            return message;
+        }
+        match decode_image(
+            image.width,
+            image.height,
+            image.encoding,
+            image.data.clone(),
+        ) {
+            Ok(decoded) => {
+                image.encoding = ImageEncoding::Raw.into();
+                image.data = decoded.bytes.into_owned();
+            }
+            Err(e) => println!("failed to convert image for peer: {}", e),
+        }
+        message
+    }
+
+    /// Returns whether `fit` may change content of `kind`, compressed images are always raw.
+    fn reduces(&self, kind: ContentKind) -> bool {
+        match kind {
+            ContentKind::Item => true,
+            ContentKind::Html => !self.supported.contains(&ContentKind::Html),
+            _ => false,
+        }
+    }
+}
+
+/// Peers that completed handshake but haven't opened `Changed` stream yet.
+#[derive(Clone, Default)]
+pub struct Sessions {
+    pending: Arc<Mutex<HashMap<String, Pending>>>,
+}
+
+struct Pending {
+    hello: Hello,
+    opened: Instant,
+}
+
+impl Sessions {
+    /// Validates peer's `Hello` and returns id of the new session.
+    #[allow(clippy::result_large_err)]
+    pub fn open(&self, hello: Hello) -> Result<String, Status> {
+        if hello.protocol_version != VERSION {
+            return Err(Status::failed_precondition(format!(
+                "protocol version {} is not supported, server speaks version {}",
+                hello.protocol_version, VERSION
+            )));
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, session| session.opened.elapsed() < SESSION_TIMEOUT);
+        if pending.len() >= MAX_PENDING {
+            return Err(Status::resource_exhausted("too many pending handshakes"));
+        }
+        let id = Uuid::new_v4().to_string();
+        let opened = Instant::now();
+        pending.insert(id.clone(), Pending { hello, opened });
+        Ok(id)
+    }
+
+    /// Takes session referenced by `Changed` request metadata.
+    #[allow(clippy::result_large_err)]
+    pub fn take<T>(&self, request: &tonic::Request<T>) -> Result<Hello, Status> {
+        request
+            .metadata()
+            .get(SESSION_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .and_then(|id| self.pending.lock().unwrap().remove(id))
+            .filter(|session| session.opened.elapsed() < SESSION_TIMEOUT)
+            .map(|session| session.hello)
+            .ok_or_else(|| Status::failed_precondition("handshake is required before streaming"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changed(id: &str) -> tonic::Request<()> {
+        let mut request = tonic::Request::new(());
+        request
+            .metadata_mut()
+            .insert(SESSION_HEADER, id.parse().unwrap());
+        request
+    }
+
+    #[test]
+    fn session_is_taken_once() {
+        let sessions = Sessions::default();
+        let id = sessions.open(hello()).unwrap();
+        assert!(sessions.take(&changed(&id)).is_ok());
+        assert!(sessions.take(&changed(&id)).is_err());
+    }
+
+    #[test]
+    fn expired_session_is_refused() {
+        let sessions = Sessions::default();
+        let id = sessions.open(hello()).unwrap();
+        let mut pending = sessions.pending.lock().unwrap();
+        let session = pending.get_mut(&id).unwrap();
+        session.opened = session.opened.checked_sub(SESSION_TIMEOUT).unwrap();
+        drop(pending);
+
+        assert!(sessions.take(&changed(&id)).is_err());
+    }
+
+    #[test]
+    fn pending_sessions_are_bounded() {
+        let sessions = Sessions::default();
+        let ids: Vec<_> = (0..MAX_PENDING)
+            .map(|_| sessions.open(hello()).unwrap())
+            .collect();
+        let refused = sessions.open(hello()).unwrap_err();
+        assert_eq!(refused.code(), tonic::Code::ResourceExhausted);
+
+        // expired ones make room
+        for session in sessions.pending.lock().unwrap().values_mut() {
+            session.opened = session.opened.checked_sub(SESSION_TIMEOUT).unwrap();
+        }
+        assert!(sessions.open(hello()).is_ok());
+        assert!(sessions.take(&changed(&ids[0])).is_err());
+    }
+
+    fn peer(supported: &[ContentKind]) -> Capabilities {
+        Capabilities::from(&Hello {
+            supported: supported.iter().map(|kind| (*kind).into()).collect(),
+            ..hello()
+        })
+    }
+
+    fn html() -> Message {
+        Message {
+            payload: Some(Body::Html(message::Html {
+                html: "<b>bold</b> ".repeat(1000),
+                alt_text: "bold ".repeat(1000),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Payload of `message`, compressed or not.
+    fn payload(message: Message) -> Option<Body> {
+        match message.payload {
+            Some(Body::Compressed(compressed)) => decompress(&compressed).unwrap().payload,
+            payload => payload,
+        }
+    }
+
+    #[test]
+    fn compressed_content_is_left_as_is_when_it_fits() {
+        let message = compress(html(), Compression::Zstd);
+        let everything = peer(&[ContentKind::Text, ContentKind::Html, ContentKind::Item]);
+        assert_eq!(everything.fit(message.clone()), message);
+    }
+
+    #[test]
+    fn compressed_content_is_reduced() {
+        let text_only = peer(&[ContentKind::Text]);
+        let html = compress(html(), Compression::Zstd);
+        let Some(Body::Text(text)) = payload(text_only.fit(html)) else {
+            panic!("html was not reduced to text");
+        };
+        assert_eq!(text.text, "bold ".repeat(1000));
+
+        let item = Message {
+            payload: Some(Body::Item(message::Item {
+                representations: vec![
+                    self::html(),
+                    Message {
+                        payload: Some(Body::Text(message::Text {
+                            text: "bold".to_owned(),
+                        })),
+                        ..Default::default()
+                    },
+                ],
+            })),
+            ..Default::default()
+        };
+        let item = compress(item, Compression::Gzip);
+        for peer in [text_only, peer(&[ContentKind::Text, ContentKind::Item])] {
+            match payload(peer.fit(item.clone())) {
+                Some(Body::Text(_)) => {}
+                Some(Body::Item(item)) => assert!(matches!(
+                    item.representations[0].payload,
+                    Some(Body::Text(_))
+                )),
+                other => panic!("item was not reduced: {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+mod handshake;
+
+pub use handshake::{channel, hello, Capabilities, Sessions, DEFAULT_CHANNEL, SESSION_HEADER};
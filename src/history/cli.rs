@@ -0,0 +1,117 @@
+use crate::client::Endpoint;
+use crate::clipboard::Selection;
+use crate::crypto::Cipher;
+use crate::message::{ClearHistoryRequest, GetEntryRequest, ListHistoryRequest};
+use crate::payload::ClipboardItem;
+use arboard::Clipboard;
+use clap::Subcommand;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum HistoryAction {
+    /// List newest entries
+    List {
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: u32,
+    },
+    /// Copy entry back into local clipboard
+    Copy { id: u64 },
+    /// Remove every entry of the channel
+    Clear,
+}
+
+/// Runs `history` subcommand against server at `endpoint`.
+pub async fn run(
+    endpoint: Endpoint,
+    cipher: Cipher,
+    action: HistoryAction,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let host = endpoint.resolve().await?;
+    let mut client = endpoint.connect(&host).await?;
+    let channel = endpoint.channel.clone();
+
+    match action {
+        HistoryAction::List { limit } => {
+            let history = client
+                .list_history(ListHistoryRequest { channel, limit })
+                .await?
+                .into_inner();
+            if history.entries.is_empty() {
+                println!("history is empty");
+            }
+            for entry in history.entries {
+                println!(
+                    "{:>6}  {:>8}  {:<12}  {:<6}  {}",
+                    entry.id,
+                    age(entry.timestamp),
+                    entry.hostname,
+                    entry
+                        .kind()
+                        .as_str_name()
+                        .trim_start_matches("CONTENT_KIND_"),
+                    if entry.preview.is_empty() {
+                        format!("({} bytes)", entry.size)
+                    } else {
+                        entry.preview
+                    }
+                );
+            }
+        }
+        HistoryAction::Copy { id } => {
+            let entry = client
+                .get_entry(GetEntryRequest { channel, id })
+                .await?
+                .into_inner();
+            let message = entry.message.ok_or("entry has no content")?;
+            let (_, item) = ClipboardItem::decode(cipher.open(message)?)?;
+
+            let selection = Selection::Clipboard;
+            if selection.offers_targets() {
+                // content is owned by this process on Linux, keep serving it until replaced
+                #[cfg(all(
+                    unix,
+                    not(any(
+                        target_os = "macos",
+                        target_os = "ios",
+                        target_os = "android",
+                        target_os = "emscripten"
+                    ))
+                ))]
+                println!(
+                    "copied entry {}, waiting until something else is copied",
+                    id
+                );
+                selection.offer(item.targets()?, true)?;
+            } else {
+                let mut clipboard = Clipboard::new()?;
+                let set = selection
+                    .set(&mut clipboard)
+                    .ok_or("clipboard is not available")?;
+                item.write(selection, set)?;
+            }
+        }
+        HistoryAction::Clear => {
+            let removed = client
+                .clear_history(ClearHistoryRequest { channel })
+                .await?
+                .into_inner()
+                .removed;
+            println!("removed {} entries", removed);
+        }
+    }
+    Ok(())
+}
+
+/// Human readable time since `timestamp` in milliseconds.
+fn age(timestamp: i64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let elapsed = now.saturating_sub(Duration::from_millis(timestamp.max(0) as u64));
+    match elapsed.as_secs() {
+        secs @ 0..60 => format!("{}s ago", secs),
+        secs @ 60..3600 => format!("{}m ago", secs / 60),
+        secs @ 3600..86400 => format!("{}h ago", secs / 3600),
+        secs => format!("{}d ago", secs / 86400),
+    }
+}
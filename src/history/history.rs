@@ -0,0 +1,414 @@
+use crate::clock::now;
+use crate::message::{message::Payload as Body, HistoryEntry, Message};
+use crate::payload::{content_kind, decompress};
+use prost::bytes::Buf;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Characters of text shown in entry preview.
+const PREVIEW_LENGTH: usize = 60;
+
+/// Bounded log of clipboard changes seen by the server.
+///
+/// When backed by a file, entries are appended to it as length-delimited `HistoryEntry`
+/// records and the file is compacted once it holds twice as many records as the history.
+/// The file is written by its own thread, so recording never waits for the disk.
+/// Disabled history ignores everything.
+#[derive(Clone, Default)]
+pub struct History {
+    store: Option<Arc<Mutex<Store>>>,
+}
+
+struct Store {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+    next_id: u64,
+    writer: Option<mpsc::Sender<Change>>,
+    /// Thread applying changes of `writer`
+    thread: Option<JoinHandle<()>>,
+    appended: usize,
+}
+
+/// Change of the history file.
+enum Change {
+    /// Record of a new entry
+    Append(Vec<u8>),
+    /// Records of every entry, replacing whole file
+    Rewrite(Vec<u8>),
+}
+
+impl History {
+    /// Keeps last `capacity` entries, loading previous ones from `path` if given.
+    pub fn new(capacity: usize, path: Option<PathBuf>) -> io::Result<Self> {
+        if capacity == 0 {
+            return Ok(Self::default());
+        }
+
+        let mut store = Store {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            next_id: 1,
+            writer: None,
+            thread: None,
+            appended: 0,
+        };
+        if let Some(path) = path {
+            if path.exists() {
+                let mut buf = prost::bytes::Bytes::from(std::fs::read(&path)?);
+                while buf.has_remaining() {
+                    match <HistoryEntry as prost::Message>::decode_length_delimited(&mut buf) {
+                        Ok(entry) => store.push(entry),
+                        Err(e) => {
+                            println!("history file {} is truncated: {}", path.display(), e);
+                            break;
+                        }
+                    }
+                }
+                store.next_id = store.entries.back().map_or(1, |entry| entry.id + 1);
+            }
+            let file = rewrite(&path, &store.records())?;
+            let (tx, rx) = mpsc::channel();
+            store.thread = Some(std::thread::spawn(move || write(path, file, rx)));
+            store.writer = Some(tx);
+        }
+
+        Ok(Self {
+            store: Some(Arc::new(Mutex::new(store))),
+        })
+    }
+
+    pub fn record(&self, message: &Message, hostname: &str, channel: &str) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let Some(kind) = content_kind(message) else {
+            return;
+        };
+
+        let mut store = store.lock().unwrap();
+        let entry = HistoryEntry {
+            id: store.next_id,
+            timestamp: now(),
+            hostname: hostname.to_owned(),
+            channel: channel.to_owned(),
+            kind: kind.into(),
+            size: prost::Message::encoded_len(message) as u64,
+            preview: preview(message),
+            message: Some(message.clone()),
+        };
+        store.next_id += 1;
+        let record = prost::Message::encode_length_delimited_to_vec(&entry);
+        store.push(entry);
+        store.append(record);
+    }
+
+    /// Newest `limit` entries of `channel`, without content.
+    pub fn list(&self, channel: &str, limit: usize) -> Vec<HistoryEntry> {
+        let Some(store) = &self.store else {
+            return vec![];
+        };
+
+        store
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.channel == channel)
+            .take(limit)
+            .map(|entry| HistoryEntry {
+                message: None,
+                ..entry.clone()
+            })
+            .collect()
+    }
+
+    pub fn get(&self, channel: &str, id: u64) -> Option<HistoryEntry> {
+        let store = self.store.as_ref()?.lock().unwrap();
+        store
+            .entries
+            .iter()
+            .find(|entry| entry.id == id && entry.channel == channel)
+            .cloned()
+    }
+
+    /// Removes every entry of `channel`, returns how many there were.
+    pub fn clear(&self, channel: &str) -> u64 {
+        let Some(store) = &self.store else {
+            return 0;
+        };
+
+        let mut store = store.lock().unwrap();
+        let before = store.entries.len();
+        store.entries.retain(|entry| entry.channel != channel);
+        let removed = (before - store.entries.len()) as u64;
+        store.compact();
+        removed
+    }
+}
+
+impl Store {
+    fn push(&mut self, entry: HistoryEntry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Appends already pushed entry to the file.
+    fn append(&mut self, record: Vec<u8>) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        writer.send(Change::Append(record)).ok();
+
+        self.appended += 1;
+        if self.appended >= self.capacity {
+            self.compact();
+        }
+    }
+
+    /// Rewrites file with current entries only.
+    fn compact(&mut self) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        writer.send(Change::Rewrite(self.records())).ok();
+        self.appended = 0;
+    }
+
+    /// Content of the file holding current entries only.
+    fn records(&self) -> Vec<u8> {
+        let mut content = vec![];
+        for entry in &self.entries {
+            content.extend(prost::Message::encode_length_delimited_to_vec(entry));
+        }
+        content
+    }
+}
+
+impl Drop for Store {
+    /// Waits until every change is in the file.
+    fn drop(&mut self) {
+        self.writer.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Applies changes to the history file at `path` until history is gone.
+fn write(path: PathBuf, mut file: File, changes: mpsc::Receiver<Change>) {
+    for change in changes {
+        let written = match change {
+            Change::Append(record) => file.write_all(&record),
+            Change::Rewrite(content) => rewrite(&path, &content).map(|new| file = new),
+        };
+        if let Err(e) = written {
+            println!("failed to write history: {}", e);
+        }
+    }
+}
+
+/// Replaces content of file at `path`, returns it open for appending.
+///
+/// Entries hold clipboard content, so the file is readable by current user only.
+fn rewrite(path: &Path, content: &[u8]) -> io::Result<File> {
+    let tmp = path.with_extension("tmp");
+    // left over by a crash, possibly with other permissions
+    match std::fs::remove_file(&tmp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut file = create_private(&tmp)?;
+    file.write_all(content)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(file)
+}
+
+/// Creates new file at `path` that only current user may access.
+fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+fn preview(message: &Message) -> String {
+    let text = match &message.payload {
+        Some(Body::Text(text)) => &text.text,
+        Some(Body::Html(html)) => &html.alt_text,
+        Some(Body::Rtf(rtf)) => &rtf.alt_text,
+        Some(Body::Image(image)) => return format!("{}x{} image", image.width, image.height),
+        Some(Body::Files(files)) => {
+            return files
+                .files
+                .iter()
+                .map(|file| file.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+        Some(Body::Compressed(compressed)) => {
+            return decompress(compressed)
+                .map(|inner| preview(&inner))
+                .unwrap_or_default()
+        }
+        Some(Body::Item(item)) => {
+            return item
+                .representations
+                .iter()
+                .map(preview)
+                .find(|preview| !preview.is_empty())
+                .unwrap_or_default()
+        }
+        Some(Body::Sealed(_)) | Some(Body::Chunk(_)) | None => return String::new(),
+    };
+
+    let line = text.trim().lines().next().unwrap_or_default();
+    let mut preview: String = line.chars().take(PREVIEW_LENGTH).collect();
+    if preview.len() < text.trim().len() {
+        preview.push('…');
+    }
+    preview
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Text;
+    use uuid::Uuid;
+
+    /// History file removed once the test is done.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!("lanclip-history-{}", Uuid::new_v4())))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+            std::fs::remove_file(self.0.with_extension("tmp")).ok();
+        }
+    }
+
+    fn text(text: &str) -> Message {
+        Message {
+            payload: Some(Body::Text(Text {
+                text: text.to_owned(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn ids(entries: Vec<HistoryEntry>) -> Vec<u64> {
+        entries.into_iter().map(|entry| entry.id).collect()
+    }
+
+    #[test]
+    fn entries_are_kept_per_channel() {
+        let history = History::new(10, None).unwrap();
+        for content in ["first", "second", "third"] {
+            history.record(&text(content), "host", "default");
+        }
+        history.record(&text("other"), "host", "design");
+
+        let listed = history.list("default", 2);
+        assert!(listed.iter().all(|entry| entry.message.is_none()));
+        assert_eq!(ids(listed), [3, 2]);
+        let entry = history.get("default", 1).unwrap();
+        assert_eq!(entry.preview, "first");
+        assert_eq!(entry.message, Some(text("first")));
+        assert_eq!(history.get("design", 1), None);
+
+        assert_eq!(history.clear("default"), 3);
+        assert!(history.list("default", 10).is_empty());
+        assert_eq!(ids(history.list("design", 10)), [4]);
+    }
+
+    #[test]
+    fn oldest_entries_are_dropped() {
+        let history = History::new(2, None).unwrap();
+        for content in ["first", "second", "third"] {
+            history.record(&text(content), "host", "default");
+        }
+        assert_eq!(ids(history.list("default", 10)), [3, 2]);
+        assert_eq!(history.get("default", 1), None);
+
+        let disabled = History::new(0, None).unwrap();
+        disabled.record(&text("first"), "host", "default");
+        assert!(disabled.list("default", 10).is_empty());
+    }
+
+    #[test]
+    fn entries_are_loaded_after_restart() {
+        let file = TempFile::new();
+        let history = History::new(4, Some(file.0.clone())).unwrap();
+        history.record(&text("first"), "host", "default");
+        history.record(&text("second"), "host", "default");
+        drop(history);
+
+        let history = History::new(4, Some(file.0.clone())).unwrap();
+        assert_eq!(ids(history.list("default", 10)), [2, 1]);
+        history.record(&text("third"), "host", "default");
+        assert_eq!(ids(history.list("default", 10)), [3, 2, 1]);
+
+        history.clear("default");
+        drop(history);
+        let history = History::new(4, Some(file.0.clone())).unwrap();
+        assert!(history.list("default", 10).is_empty());
+    }
+
+    #[test]
+    fn file_is_compacted() {
+        let file = TempFile::new();
+        let history = History::new(2, Some(file.0.clone())).unwrap();
+        for content in ["1", "2", "3", "4", "5"] {
+            history.record(&text(content), "host", "default");
+        }
+        drop(history);
+
+        let mut buf = prost::bytes::Bytes::from(std::fs::read(&file.0).unwrap());
+        let mut records = 0;
+        while buf.has_remaining() {
+            <HistoryEntry as prost::Message>::decode_length_delimited(&mut buf).unwrap();
+            records += 1;
+        }
+        assert!(records <= 4, "{} records", records);
+        let history = History::new(2, Some(file.0.clone())).unwrap();
+        assert_eq!(ids(history.list("default", 10)), [5, 4]);
+    }
+
+    #[test]
+    fn rewritten_file_is_open_for_appending() {
+        let file = TempFile::new();
+        std::fs::write(&file.0, b"old content").unwrap();
+        let mut rewritten = rewrite(&file.0, b"new").unwrap();
+        rewritten.write_all(b" record").unwrap();
+
+        assert_eq!(std::fs::read(&file.0).unwrap(), b"new record");
+        assert!(!file.0.with_extension("tmp").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let file = TempFile::new();
+        std::fs::write(&file.0, b"").unwrap();
+        std::fs::set_permissions(&file.0, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let history = History::new(4, Some(file.0.clone())).unwrap();
+        history.record(&text("secret"), "host", "default");
+
+        let mode = std::fs::metadata(&file.0).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
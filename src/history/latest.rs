@@ -0,0 +1,133 @@
+use crate::clock;
+use crate::history::History;
+use crate::message::{Message, Selection};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Last content seen in every channel, pushed to peers joining later.
+///
+/// Only CLIPBOARD content is kept, PRIMARY changes with every highlight and would replace it.
+///
+/// Every content gets the next revision of the server, peers echo the last one they received
+/// when they reconnect. Revisions start at the server start time in milliseconds, so they keep
+/// growing across restarts.
+#[derive(Clone)]
+pub struct Latest {
+    channels: Arc<Mutex<Channels>>,
+}
+
+struct Channels {
+    items: HashMap<String, Message>,
+    revision: u64,
+}
+
+impl Default for Latest {
+    fn default() -> Self {
+        Self {
+            channels: Arc::new(Mutex::new(Channels {
+                items: HashMap::new(),
+                revision: clock::now() as u64,
+            })),
+        }
+    }
+}
+
+impl Latest {
+    /// Keeps `message` as the last content of `channel` unless it is PRIMARY one, returns
+    /// revision it got.
+    pub fn set(&self, channel: &str, message: &Message) -> u64 {
+        let mut channels = self.channels.lock().unwrap();
+        channels.revision += 1;
+        let revision = channels.revision;
+        if message.selection() != Selection::Clipboard {
+            return revision;
+        }
+        channels.items.insert(
+            channel.to_owned(),
+            Message {
+                revision,
+                ..message.clone()
+            },
+        );
+        revision
+    }
+
+    /// Last content of `channel` if its revision is above `revision`.
+    pub fn newer_than(&self, channel: &str, revision: u64) -> Option<Message> {
+        self.channels
+            .lock()
+            .unwrap()
+            .items
+            .get(channel)
+            .filter(|message| message.revision > revision)
+            .cloned()
+    }
+
+    /// Revision of the last content of `channel`, 0 if none was seen.
+    pub fn revision(&self, channel: &str) -> u64 {
+        let channels = self.channels.lock().unwrap();
+        channels
+            .items
+            .get(channel)
+            .map_or(0, |message| message.revision)
+    }
+}
+
+/// Keeps content arriving from single source in history and as the latest of its channel.
+#[derive(Clone)]
+pub struct Recorder {
+    pub history: History,
+    pub latest: Latest,
+    pub hostname: String,
+    pub channel: String,
+}
+
+impl Recorder {
+    /// Returns revision `message` got, content sent on carries it.
+    pub fn record(&self, message: &Message) -> u64 {
+        self.history.record(message, &self.hostname, &self.channel);
+        self.latest.set(&self.channel, message)
+    }
+
+    /// Revision of the last content of the channel.
+    pub fn revision(&self) -> u64 {
+        self.latest.revision(&self.channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_above_echoed_revision_is_newer() {
+        let latest = Latest::default();
+        assert_eq!(latest.newer_than("default", 0), None);
+
+        let first = latest.set("default", &Message::default());
+        let second = latest.set("design", &Message::default());
+        assert!(second > first);
+        assert_eq!(latest.revision("design"), second);
+
+        let pushed = latest.newer_than("default", first - 1);
+        assert_eq!(pushed.map(|message| message.revision), Some(first));
+        assert_eq!(latest.newer_than("default", first), None);
+    }
+
+    #[test]
+    fn primary_change_does_not_replace_clipboard() {
+        let latest = Latest::default();
+        let clipboard = latest.set("default", &Message::default());
+        let primary = latest.set(
+            "default",
+            &Message {
+                selection: Selection::Primary.into(),
+                ..Default::default()
+            },
+        );
+        assert!(primary > clipboard);
+
+        let pushed = latest.newer_than("default", 0);
+        assert_eq!(pushed.map(|message| message.revision), Some(clipboard));
+    }
+}
@@ -0,0 +1,8 @@
+mod cli;
+#[allow(clippy::module_inception)]
+mod history;
+mod latest;
+
+pub use cli::{run, HistoryAction};
+pub use history::History;
+pub use latest::{Latest, Recorder};
@@ -1,44 +1,382 @@
-use crate::message::Message;
+use crate::clock;
+use crate::handshake::{self, Capabilities};
+use crate::message::{Hello, Message};
+use crate::payload;
+use clap::Args;
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc::Sender;
-use tokio::sync::oneshot::Receiver;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 
+/// Items waiting for a follower by default.
+pub const DEFAULT_QUEUE_SIZE: usize = 8;
+
+/// What happens to content for a follower that doesn't keep up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SlowConsumer {
+    /// The oldest waiting item is dropped
+    DropOldest,
+    /// Waiting items are replaced by the latest one
+    #[default]
+    Latest,
+    /// Follower is disconnected, it gets the current content when it reconnects
+    Disconnect,
+}
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct Queue {
+    /// Items waiting for a single slow follower before `--slow-consumer` applies
+    #[arg(long = "queue-size", default_value_t = DEFAULT_QUEUE_SIZE,
+          value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub size: usize,
+    /// What happens when queue of a follower is full
+    #[arg(long = "slow-consumer", value_enum, default_value_t)]
+    pub policy: SlowConsumer,
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_QUEUE_SIZE,
+            policy: SlowConsumer::default(),
+        }
+    }
+}
+
+/// Counters of a single follower.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Items waiting to be sent
+    pub queued: usize,
+    /// Items fully handed over to the stream
+    pub sent: u64,
+    /// Items dropped or replaced because follower was behind
+    pub dropped: u64,
+    /// Encoded size of everything handed over to the stream
+    pub bytes: u64,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent {} ({} bytes), dropped {}, queued {}",
+            self.sent, self.bytes, self.dropped, self.queued
+        )
+    }
+}
+
+/// Who is on the other end of a follower.
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub id: String,
+    pub addr: Option<SocketAddr>,
+    pub hostname: String,
+    pub os: String,
+    pub channel: String,
+    /// Milliseconds since unix epoch
+    pub connected_since: i64,
+    pub stats: Stats,
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.addr {
+            Some(addr) => write!(f, "{}", addr)?,
+            None => write!(f, "{}", self.id)?,
+        }
+        write!(
+            f,
+            " ({}, {}) in channel: {}",
+            self.hostname, self.os, self.channel
+        )
+    }
+}
+
+/// Connected peer content is sent to.
+///
+/// Every follower has its own task draining a bounded queue into the stream, so `send` never
+/// waits and one slow follower doesn't hold up the others. Content is fit for the peer by that
+/// task too, as it may decode the whole item.
 pub struct Follower {
-    receiver: Receiver<()>,
-    sender: Sender<Result<Message, tonic::Status>>,
+    sender: Option<Sender<Result<Message, tonic::Status>>>,
     state: Status,
     id: String,
+    capabilities: Capabilities,
+    channel: String,
+    addr: Option<SocketAddr>,
+    hostname: String,
+    os: String,
+    connected_since: i64,
+    queue: Queue,
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum Status {
     WORKING,
     STOPPED,
 }
 
+/// State shared with the sending task.
+#[derive(Default)]
+struct Shared {
+    pending: Mutex<VecDeque<Result<Message, tonic::Status>>>,
+    ready: Notify,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    bytes: AtomicU64,
+}
+
 impl Follower {
-    pub fn new(sender: Sender<Result<Message, tonic::Status>>, receiver: Receiver<()>) -> Self {
+    /// Follower of peer that introduced itself with `hello`, from `addr` if it is known.
+    ///
+    /// Must be called within tokio runtime, it spawns the sending task.
+    pub fn new(
+        sender: Sender<Result<Message, tonic::Status>>,
+        hello: &Hello,
+        addr: Option<SocketAddr>,
+        queue: Queue,
+    ) -> Self {
+        let shared = Arc::new(Shared::default());
+        let capabilities = Capabilities::from(hello);
+        let task = tokio::spawn(deliver(
+            shared.clone(),
+            sender.clone(),
+            capabilities.clone(),
+        ));
         Self {
-            receiver,
-            sender,
+            sender: Some(sender),
             state: Status::WORKING,
             id: Uuid::new_v4().to_string(),
+            capabilities,
+            channel: handshake::channel(hello).to_owned(),
+            addr,
+            hostname: hello.hostname.clone(),
+            os: hello.os.clone(),
+            connected_since: clock::now(),
+            queue,
+            shared,
+            task,
         }
     }
 
     pub fn is_working(&self) -> bool {
-        matches!(self.state, Status::WORKING)
+        matches!(self.state, Status::WORKING) && !self.task.is_finished()
     }
 
+    /// Queues message for sending, never waits for the peer.
     pub fn send(&mut self, message: Result<Message, tonic::Status>) {
-        if let Ok(_) = self.receiver.try_recv() {
-            self.state = Status::STOPPED;
+        if !self.is_working() {
             return;
         }
 
-        self.sender.blocking_send(message).ok();
+        let mut pending = self.shared.pending.lock().unwrap();
+        if pending.len() >= self.queue.size {
+            let dropped = match self.queue.policy {
+                SlowConsumer::DropOldest => pending.pop_front().map_or(0, |_| 1),
+                SlowConsumer::Latest => pending.drain(..).count() as u64,
+                SlowConsumer::Disconnect => {
+                    drop(pending);
+                    self.disconnect();
+                    return;
+                }
+            };
+            self.shared.dropped.fetch_add(dropped, Ordering::Relaxed);
+            println!(
+                "follower {} is behind, dropped {} item(s)",
+                self.id, dropped
+            );
+        }
+        pending.push_back(message);
+        drop(pending);
+        self.shared.ready.notify_one();
+    }
+
+    /// Closes stream of follower that fell behind, it resyncs once it reconnects.
+    fn disconnect(&mut self) {
+        println!("disconnecting slow follower {}: {}", self.id, self.stats());
+        self.task.abort();
+        self.shared.pending.lock().unwrap().clear();
+        if let Some(sender) = self.sender.take() {
+            sender
+                .try_send(Err(tonic::Status::resource_exhausted(
+                    "follower is too slow",
+                )))
+                .ok();
+        }
+        self.state = Status::STOPPED;
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            queued: self.shared.pending.lock().unwrap().len(),
+            sent: self.shared.sent.load(Ordering::Relaxed),
+            dropped: self.shared.dropped.load(Ordering::Relaxed),
+            bytes: self.shared.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn connection(&self) -> Connection {
+        Connection {
+            id: self.id.clone(),
+            addr: self.addr,
+            hostname: self.hostname.clone(),
+            os: self.os.clone(),
+            channel: self.channel.clone(),
+            connected_since: self.connected_since,
+            stats: self.stats(),
+        }
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
     }
 
     pub fn id(&self) -> &String {
         &self.id
     }
+
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+}
+
+impl Drop for Follower {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Sends queued messages in order until the stream is closed, the ones peer can't receive
+/// even after fitting them are skipped.
+async fn deliver(
+    shared: Arc<Shared>,
+    sender: Sender<Result<Message, tonic::Status>>,
+    capabilities: Capabilities,
+) {
+    loop {
+        let next = shared.pending.lock().unwrap().pop_front();
+        let message = match next {
+            Some(Ok(message)) => {
+                let capabilities = capabilities.clone();
+                let fitted = tokio::task::spawn_blocking(move || {
+                    let message = capabilities.fit(message);
+                    capabilities.accepts(&message).then_some(message)
+                });
+                match fitted.await {
+                    Ok(Some(message)) => Ok(message),
+                    _ => continue,
+                }
+            }
+            Some(Err(status)) => Err(status),
+            None => {
+                shared.ready.notified().await;
+                continue;
+            }
+        };
+        for chunk in chunks(message) {
+            let size = chunk.as_ref().map_or(0, prost::Message::encoded_len);
+            if sender.send(chunk).await.is_err() {
+                return;
+            }
+            shared.bytes.fetch_add(size as u64, Ordering::Relaxed);
+        }
+        shared.sent.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Splits large message into chunks, errors are passed through as is.
+fn chunks(message: Result<Message, tonic::Status>) -> Vec<Result<Message, tonic::Status>> {
+    match message {
+        Ok(message) => payload::split(message).into_iter().map(Ok).collect(),
+        Err(status) => vec![Err(status)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{message::Payload as Body, Text};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    type Stream = mpsc::Receiver<Result<Message, tonic::Status>>;
+
+    fn text(text: &str) -> Message {
+        Message {
+            payload: Some(Body::Text(Text {
+                text: text.to_owned(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Follower with two items in flight that nobody reads and a full queue.
+    async fn behind(policy: SlowConsumer) -> (Follower, Stream) {
+        let (tx, rx) = mpsc::channel(1);
+        let mut follower = Follower::new(tx, &handshake::hello(), None, Queue { size: 2, policy });
+        follower.send(Ok(text("1")));
+        follower.send(Ok(text("2")));
+        // first one sits in the stream, the second one waits for free slot
+        while follower.stats().queued > 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        follower.send(Ok(text("3")));
+        follower.send(Ok(text("4")));
+        (follower, rx)
+    }
+
+    async fn received(stream: &mut Stream) -> Option<String> {
+        match stream.recv().await? {
+            Ok(Message {
+                payload: Some(Body::Text(text)),
+                ..
+            }) => Some(text.text),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn oldest_item_is_dropped() {
+        let (mut follower, mut stream) = behind(SlowConsumer::DropOldest).await;
+        follower.send(Ok(text("5")));
+        assert_eq!(follower.stats().dropped, 1);
+
+        for expected in ["1", "2", "4", "5"] {
+            assert_eq!(received(&mut stream).await.as_deref(), Some(expected));
+        }
+        assert!(follower.is_working());
+    }
+
+    #[tokio::test]
+    async fn waiting_items_are_replaced_by_latest() {
+        let (mut follower, mut stream) = behind(SlowConsumer::Latest).await;
+        follower.send(Ok(text("5")));
+        assert_eq!(follower.stats().dropped, 2);
+
+        for expected in ["1", "2", "5"] {
+            assert_eq!(received(&mut stream).await.as_deref(), Some(expected));
+        }
+        while follower.stats().sent < 3 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(follower.stats().queued, 0);
+    }
+
+    #[tokio::test]
+    async fn slow_follower_is_disconnected() {
+        let (mut follower, mut stream) = behind(SlowConsumer::Disconnect).await;
+        follower.send(Ok(text("5")));
+        assert!(!follower.is_working());
+
+        assert_eq!(received(&mut stream).await.as_deref(), Some("1"));
+        assert_eq!(received(&mut stream).await, None);
+    }
 }
@@ -1,116 +1,125 @@
-use crate::clipboard::{CallbackResult, ClipboardHandler, ClipboardType, Master};
-use crate::listener::follower::Follower;
+use crate::clipboard::{
+    CallbackResult, ClipboardBackend, ClipboardHandler, ClipboardType, Selection, SelectionMode,
+};
+use crate::crypto::Cipher;
+use crate::echo::{Origin, Synced};
+use crate::history::Recorder;
 use crate::message::Message;
-use arboard::Clipboard;
-use image::{DynamicImage, ImageBuffer, ImageFormat};
-use std::fs::File;
-use std::io::BufWriter;
-use std::sync::mpsc::Receiver;
+use crate::payload::Encoding;
+use crate::server::registry::Registry;
+use std::collections::HashMap;
+use std::sync::Arc;
 
 pub struct ClipboardListener {
-    followers: Vec<Follower>,
-    receiver: Receiver<String>,
-    follower_receiver: Receiver<Follower>,
-    clipboard: Clipboard,
+    followers: Registry,
+    channel: String,
+    clipboard: Arc<dyn ClipboardBackend>,
+    selections: SelectionMode,
+    cipher: Cipher,
+    recorder: Recorder,
+    origin: Origin,
+    synced: Synced,
 }
 
 impl ClipboardHandler for ClipboardListener {
     fn on_clipboard_change(&mut self, r#type: ClipboardType) -> CallbackResult {
-        while let Ok(follower) = self.follower_receiver.try_recv() {
-            self.followers.push(follower);
-        }
+        self.on_change(Selection::Clipboard, r#type)
+    }
 
-        if self.followers.is_empty() {
-            return CallbackResult::Next;
-        }
+    fn on_primary_change(&mut self, r#type: ClipboardType) -> CallbackResult {
+        self.on_change(Selection::Primary, r#type)
+    }
 
-        // remove stopped follower
-        self.followers.retain(|f| f.is_working());
+    fn selections(&self) -> SelectionMode {
+        self.selections
+    }
+}
 
-        if self.followers.is_empty() {
-            return CallbackResult::Next;
+impl ClipboardListener {
+    /// Sends changes to `followers` in `channel`, `synced` is shared with the service writing
+    /// content received from them.
+    pub fn new(
+        followers: Registry,
+        channel: String,
+        clipboard: Arc<dyn ClipboardBackend>,
+        synced: Synced,
+        selections: SelectionMode,
+        cipher: Cipher,
+        recorder: Recorder,
+    ) -> Self {
+        Self {
+            followers,
+            channel,
+            clipboard,
+            selections,
+            cipher,
+            recorder,
+            origin: Origin::default(),
+            synced,
         }
+    }
 
-        let message = match r#type {
-            ClipboardType::IMAGE => {
-                if let Ok(image) = self.clipboard.get_image() {
-                    if image.bytes.len() > 10 * 1024 * 1024 {
-                        println!("image is too large: {}", image.bytes.len());
-                        return CallbackResult::Next;
-                    }
-
-                    // test
-                    // if let Some(buffer) = ImageBuffer::from_vec(
-                    //     image.width as u32,
-                    //     image.height as u32,
-                    //     image.bytes.to_vec(),
-                    // ) {
-                    //     let image = DynamicImage::ImageRgba8(buffer);
-                    //     let file = File::create(
-                    //         "/Users/zlatan/code/RustroverProjects/LanClip/target/debug/image.png",
-                    //     )
-                    //     .unwrap();
-                    //     let mut writer = BufWriter::new(file);
-                    //     image.write_to(&mut writer, ImageFormat::Png).unwrap();
-                    // }
-
-                    let mut data = Vec::with_capacity(image.bytes.len() + 8);
-                    data.extend_from_slice(&image.width.to_le_bytes());
-                    data.extend_from_slice(&image.height.to_le_bytes());
-                    data.extend_from_slice(&image.bytes);
+    pub fn start(self) {
+        let clipboard = self.clipboard.clone();
+        clipboard.watch(Box::new(self)).unwrap();
+    }
 
-                    Ok(Message {
-                        r#type: "image".to_owned(),
-                        body: data,
-                    })
-                } else {
-                    println!("image is empty");
-                    return CallbackResult::Next;
-                }
-            }
-            ClipboardType::TEXT => {
-                if let Ok(text) = self.clipboard.get_text() {
-                    Ok(Message {
-                        r#type: "text".to_owned(),
-                        body: text.into_bytes(),
-                    })
-                } else {
-                    return CallbackResult::Next;
-                }
-            }
-            _ => return CallbackResult::Next,
+    fn on_change(&mut self, selection: Selection, r#type: ClipboardType) -> CallbackResult {
+        if matches!(r#type, ClipboardType::UNKNOWN) {
+            return CallbackResult::Next;
+        }
+        let Some(item) = self.clipboard.get(selection) else {
+            return CallbackResult::Next;
+        };
+        let hash = item.content_hash();
+        // change was already handled
+        let Some(origin) = self
+            .synced
+            .changed(selection, hash.clone(), self.origin.id())
+        else {
+            return CallbackResult::Next;
+        };
+        let stamp = self.origin.stamp(Message::default());
+        // every follower gets content in encoding it decodes, each one is encoded once
+        let mut encoded = HashMap::new();
+        let mut message = |encoding: Encoding| {
+            encoded
+                .entry(encoding)
+                .or_insert_with(|| {
+                    self.cipher
+                        .seal(Message {
+                            origin: stamp.origin.clone(),
+                            sequence: stamp.sequence,
+                            content_hash: hash.clone(),
+                            ..item.clone().encode(selection, encoding)
+                        })
+                        .map_err(|e| println!("{}", e))
+                        .ok()
+                })
+                .clone()
         };
 
-        match self.receiver.try_recv() {
-            Ok(id) => {
-                for follower in self.followers.iter_mut() {
-                    if *follower.id() != id {
-                        follower.send(message.clone());
-                    }
-                }
-            }
-            Err(_) => {
-                for follower in self.followers.iter_mut() {
-                    follower.send(message.clone());
+        let revision = if origin == self.origin.id() {
+            let Some(message) = message(Encoding::preferred()) else {
+                return CallbackResult::Next;
+            };
+            self.recorder.record(&message)
+        } else {
+            // content from followers is recorded when received
+            self.recorder.revision()
+        };
+        // content written for a follower goes to everybody else
+        self.followers.for_each(|follower| {
+            if *follower.id() != origin && follower.channel() == self.channel {
+                if let Some(message) = message(follower.capabilities().encoding()) {
+                    follower.send(Ok(Message {
+                        revision,
+                        ..message
+                    }));
                 }
             }
-        }
+        });
 
         CallbackResult::Next
     }
 }
-
-impl ClipboardListener {
-    pub fn new(receiver: Receiver<String>, follower_receiver: Receiver<Follower>) -> Self {
-        Self {
-            followers: vec![],
-            receiver,
-            follower_receiver,
-            clipboard: Clipboard::new().unwrap(),
-        }
-    }
-
-    pub fn start(self) {
-        Master::new(self).unwrap().run().unwrap();
-    }
-}
@@ -1,5 +1,6 @@
 mod follower;
+#[allow(clippy::module_inception)]
 mod listener;
 
-pub use follower::Follower;
+pub use follower::{Connection, Follower, Queue};
 pub use listener::ClipboardListener;
@@ -1,15 +1,49 @@
+mod auth;
 mod client;
+mod clipboard;
+mod clock;
+mod config;
+mod crypto;
+mod discovery;
+mod echo;
+mod handshake;
+mod history;
 mod listener;
+mod payload;
+mod peer;
 mod server;
-mod clipboard;
+#[cfg(test)]
+mod tests;
+mod tls;
 
-use crate::client::ClipboardClient;
-use crate::listener::{ClipboardListener, Follower};
+use crate::auth::ServerAuth;
+use crate::client::{ClipboardClient, ConnectArgs, Endpoint, Target};
+use crate::clipboard::{ClipboardBackend, SelectionMode, SystemClipboard};
+use crate::config::ServerConfig;
+use crate::crypto::Cipher;
+use crate::discovery::{Advertiser, DISCOVERY_PORT};
+use crate::echo::Synced;
+use crate::handshake::DEFAULT_CHANNEL;
+use crate::history::{History, HistoryAction, Recorder};
+use crate::listener::{ClipboardListener, Queue};
 use crate::message::clipboard_service_server::ClipboardServiceServer;
-use crate::server::server::ClipboardServiceImpl;
+use crate::payload::{
+    max_message_size, CompressionCodec, ImageFormat, DEFAULT_IMAGE_QUALITY, DEFAULT_MAX_ITEM_SIZE,
+};
+use crate::peer::{Mesh, Peer};
+use crate::server::registry::{self, Registry};
+use crate::server::server::{ClipboardServiceImpl, LateJoin};
+use crate::tls::{PeerTlsArgs, ServerTlsArgs};
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::codec::CompressionEncoding;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::server::TcpIncoming;
 use tonic::transport::Server;
 
+#[allow(clippy::module_inception)]
 pub mod message {
     include!(concat!(env!("OUT_DIR"), "/message.rs"));
 }
@@ -17,6 +51,22 @@ pub mod message {
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
+    /// Largest clipboard item to send or accept, in MiB
+    #[arg(long, global = true, default_value_t = DEFAULT_MAX_ITEM_SIZE)]
+    max_item_size: usize,
+    /// Encoding images are sent with, peers that don't support it receive raw pixels
+    #[arg(long, global = true, value_enum, default_value_t)]
+    image_format: ImageFormat,
+    /// JPEG quality from 1 to 100
+    #[arg(long, global = true, default_value_t = DEFAULT_IMAGE_QUALITY,
+          value_parser = clap::value_parser!(u8).range(1..=100))]
+    image_quality: u8,
+    /// Codec payloads larger than a few KiB are compressed with
+    #[arg(long, global = true, value_enum, default_value_t)]
+    compression: CompressionCodec,
+    /// Directory received files are stored in, `lanclip/inbox` in per-user data directory by default
+    #[arg(long, global = true)]
+    inbox: Option<PathBuf>,
     #[command(subcommand)]
     command: Command,
 }
@@ -26,41 +76,377 @@ enum Command {
     Server {
         #[arg(short, long)]
         port: i32,
+        /// Selections to synchronize, PRIMARY is only available on Linux
+        #[arg(long, value_enum, default_value_t, conflicts_with = "relay")]
+        selection: SelectionMode,
+        /// Only forward content between clients, never touching local clipboard
+        #[arg(long)]
+        relay: bool,
+        /// Shared secret clients must present, everyone is allowed when not set
+        #[arg(long, env = "LANCLIP_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+        /// Passphrase for end-to-end encryption of content, must match on every peer
+        #[arg(
+            long,
+            env = "LANCLIP_PASSPHRASE",
+            hide_env_values = true,
+            conflicts_with = "relay"
+        )]
+        passphrase: Option<String>,
+        #[command(flatten)]
+        tls: ServerTlsArgs,
+        /// UDP port to answer discovery probes on
+        #[arg(long, default_value_t = DISCOVERY_PORT)]
+        discovery_port: u16,
+        /// Don't answer discovery probes
+        #[arg(long)]
+        no_discovery: bool,
+        /// Channel clipboard of this machine belongs to
+        #[arg(long, default_value = DEFAULT_CHANNEL, conflicts_with = "relay")]
+        channel: String,
+        /// TOML file listing channels clients may join and their tokens
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Clipboard changes to keep in history, disabled by default
+        #[arg(long, default_value_t = 0)]
+        history: usize,
+        /// File history is kept in across restarts, readable by current user only
+        #[arg(long)]
+        history_file: Option<PathBuf>,
+        /// What newly connected clients receive before the next change
+        #[arg(long, value_enum, default_value_t)]
+        late_join: LateJoin,
+        #[command(flatten)]
+        queue: Queue,
+        /// Accept and send gzip compressed gRPC messages, for clients using `--grpc-gzip`
+        #[arg(long)]
+        grpc_gzip: bool,
     },
     Client {
+        #[command(flatten)]
+        connect: ConnectArgs,
+        /// Selections to synchronize, PRIMARY is only available on Linux
+        #[arg(long, value_enum, default_value_t)]
+        selection: SelectionMode,
+        /// Passphrase for end-to-end encryption of content, must match on every peer
+        #[arg(long, env = "LANCLIP_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+    },
+    /// Browse clipboard history kept by the server
+    History {
+        #[command(flatten)]
+        connect: ConnectArgs,
+        /// Passphrase content was encrypted with
+        #[arg(long, env = "LANCLIP_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+        #[command(subcommand)]
+        action: HistoryAction,
+    },
+    /// Serverless mode, every node serves its clipboard and links to other nodes
+    Peer {
         #[arg(short, long)]
-        addr: String,
+        port: i32,
+        /// Node to link to as `host:port`, can be repeated
+        #[arg(long = "peer", value_name = "ADDR")]
+        peers: Vec<String>,
+        /// Link to nodes found on local network
+        #[arg(long)]
+        discover: bool,
+        /// UDP port to answer and send discovery probes on
+        #[arg(long, default_value_t = DISCOVERY_PORT)]
+        discovery_port: u16,
+        /// Selections to synchronize, PRIMARY is only available on Linux
+        #[arg(long, value_enum, default_value_t)]
+        selection: SelectionMode,
+        /// Shared secret every node must present
+        #[arg(long, env = "LANCLIP_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+        /// Passphrase for end-to-end encryption of content, must match on every peer
+        #[arg(long, env = "LANCLIP_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+        /// Compress whole gRPC messages exchanged with other nodes with gzip
+        #[arg(long)]
+        grpc_gzip: bool,
+        #[command(flatten)]
+        queue: Queue,
+        #[command(flatten)]
+        tls: PeerTlsArgs,
+    },
+    /// List servers on local network
+    Discover {
+        /// UDP port servers answer discovery probes on
+        #[arg(long, default_value_t = DISCOVERY_PORT)]
+        discovery_port: u16,
+        /// Seconds to wait for answers
+        #[arg(long, default_value_t = 2)]
+        timeout: u64,
     },
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
+    payload::set_max_item_size(cli.max_item_size * 1024 * 1024);
+    payload::set_image_format(cli.image_format, cli.image_quality);
+    payload::set_compression(cli.compression);
+    if let Some(inbox) = cli.inbox {
+        payload::set_inbox(inbox);
+    }
     match cli.command {
-        Command::Client { addr } => start_client(&addr).await,
-        Command::Server { port } => start_server(port).await,
+        Command::Client {
+            connect,
+            selection,
+            passphrase,
+        } => start_client(connect.endpoint()?, selection, Cipher::new(passphrase)?).await,
+        Command::History {
+            connect,
+            passphrase,
+            action,
+        } => history::run(connect.endpoint()?, Cipher::new(passphrase)?, action).await,
+        Command::Server {
+            port,
+            selection,
+            relay,
+            token,
+            passphrase,
+            tls,
+            discovery_port,
+            no_discovery,
+            channel,
+            config,
+            history,
+            history_file,
+            late_join,
+            queue,
+            grpc_gzip,
+        } => {
+            let config = match config {
+                Some(path) => ServerConfig::load(&path)?,
+                None => ServerConfig::default(),
+            };
+            let history = History::new(history, history_file)?;
+            let tokens = token.into_iter().collect();
+            if !no_discovery {
+                let advertiser = Advertiser::new(port as u16, relay);
+                tokio::spawn(async move {
+                    if let Err(e) = advertiser.run(discovery_port).await {
+                        println!("discovery is disabled: {}", e);
+                    }
+                });
+            }
+            let service = if relay {
+                ClipboardServiceImpl::relay()
+            } else {
+                let cipher = Cipher::new(passphrase)?;
+                let clipboard = Arc::new(SystemClipboard::new()?);
+                local_service(clipboard, selection, channel, cipher, history.clone())
+            };
+            serve(
+                service
+                    .with_config(config)
+                    .with_history(history)
+                    .with_late_join(late_join)
+                    .with_queue(queue),
+                port,
+                tokens,
+                tls,
+                grpc_gzip,
+            )
+            .await
+        }
+        Command::Peer {
+            port,
+            peers,
+            discover,
+            discovery_port,
+            selection,
+            token,
+            passphrase,
+            grpc_gzip,
+            queue,
+            tls,
+        } => {
+            let cipher = Cipher::new(passphrase)?;
+            // how other nodes are reached, `target` and `tls` are set for every one of them
+            let link = Endpoint {
+                target: Target::Discover(discovery_port),
+                token,
+                channel: DEFAULT_CHANNEL.to_owned(),
+                tls: None,
+                gzip: grpc_gzip,
+            };
+            start_peer(
+                port,
+                peers,
+                discover,
+                discovery_port,
+                selection,
+                link,
+                cipher,
+                queue,
+                tls,
+            )
+            .await
+        }
+        Command::Discover {
+            discovery_port,
+            timeout,
+        } => {
+            let servers = discovery::discover(discovery_port, Duration::from_secs(timeout)).await?;
+            if servers.is_empty() {
+                println!("no server found");
+            }
+            for server in servers {
+                let announcement = server.announcement;
+                println!(
+                    "{}\t{}\t{}{}",
+                    server.addr,
+                    announcement.hostname,
+                    announcement.instance_id,
+                    if announcement.relay { "\trelay" } else { "" }
+                );
+            }
+            Ok(())
+        }
     }
 }
 
-async fn start_client(host: &str) -> Result<(), Box<dyn std::error::Error>> {
-    ClipboardClient::new(host).start().await
+async fn start_client(
+    endpoint: Endpoint,
+    selection: SelectionMode,
+    cipher: Cipher,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let clipboard = Arc::new(SystemClipboard::new()?);
+    ClipboardClient::new(endpoint, clipboard, selection, cipher)
+        .start()
+        .await
 }
 
-async fn start_server(port: i32) -> Result<(), Box<dyn std::error::Error>> {
-    let addr = format!("0.0.0.0:{}", port);
-    let (tx, rx) = std::sync::mpsc::channel::<String>();
-    let (ftx, frx) = std::sync::mpsc::channel::<Follower>();
+/// Service syncing clipboard of this machine, spawns `ClipboardListener` watching it.
+fn local_service(
+    clipboard: Arc<dyn ClipboardBackend>,
+    selection: SelectionMode,
+    channel: String,
+    cipher: Cipher,
+    history: History,
+) -> ClipboardServiceImpl {
+    let followers = Registry::default();
+    let synced = Synced::default();
 
+    let service = ClipboardServiceImpl::new(
+        followers.clone(),
+        clipboard.clone(),
+        synced.clone(),
+        selection,
+        cipher.clone(),
+        channel.clone(),
+    );
+    let recorder = Recorder {
+        history,
+        latest: service.latest(),
+        hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+        channel: channel.clone(),
+    };
     std::thread::spawn(move || {
-        let listener = ClipboardListener::new(rx, frx);
+        let listener = ClipboardListener::new(
+            followers, channel, clipboard, synced, selection, cipher, recorder,
+        );
         listener.start();
     });
 
-    Server::builder()
-        .add_service(ClipboardServiceServer::new(ClipboardServiceImpl::new(
-            tx, ftx,
-        )))
-        .serve(addr.parse()?)
+    service
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn start_peer(
+    port: i32,
+    peers: Vec<String>,
+    discover: bool,
+    discovery_port: u16,
+    selection: SelectionMode,
+    link: Endpoint,
+    cipher: Cipher,
+    queue: Queue,
+    tls: PeerTlsArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    let mesh = Mesh::new(tx, queue);
+    println!("node id: {}", mesh.origin());
+
+    let advertiser = Advertiser::peer(port as u16, mesh.origin().to_owned());
+    tokio::spawn(async move {
+        if let Err(e) = advertiser.run(discovery_port).await {
+            println!("discovery is disabled: {}", e);
+        }
+    });
+
+    let clipboard = Arc::new(SystemClipboard::new()?);
+    let peer = Peer::new(mesh.clone(), rx, clipboard, selection, cipher);
+    let client_tls = tls.client();
+    for addr in peers {
+        peer.dial(Endpoint {
+            tls: client_tls.config(&addr)?,
+            target: Target::Address(addr),
+            ..link.clone()
+        });
+    }
+    let tokens = link.token.iter().cloned().collect();
+    let gzip = link.gzip;
+    if discover {
+        // discovered nodes are only known by address, `--tls-domain` names them
+        let tls = client_tls.config("")?;
+        peer.discover(discovery_port, Endpoint { tls, ..link });
+    }
+
+    tokio::try_join!(
+        serve(
+            ClipboardServiceImpl::mesh(mesh),
+            port,
+            tokens,
+            tls.server(),
+            gzip
+        ),
+        peer.start()
+    )?;
+    Ok(())
+}
+
+async fn serve(
+    service: ClipboardServiceImpl,
+    port: i32,
+    tokens: Vec<String>,
+    tls: ServerTlsArgs,
+    gzip: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let addr = format!("0.0.0.0:{}", port);
+    let incoming = TcpIncoming::bind(addr.parse()?)?.with_nodelay(Some(true));
+    serve_incoming(service, incoming, tokens, tls, gzip).await
+}
+
+/// Same as `serve`, on connections accepted by `incoming`.
+async fn serve_incoming(
+    service: ClipboardServiceImpl,
+    incoming: TcpIncoming,
+    tokens: Vec<String>,
+    tls: ServerTlsArgs,
+    gzip: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    tokio::spawn(registry::log(service.registry().subscribe()));
+    let mut builder = Server::builder();
+    if let Some(tls) = tls.config()? {
+        builder = builder.tls_config(tls)?;
+    }
+    let mut server = ClipboardServiceServer::new(service)
+        .max_decoding_message_size(max_message_size())
+        .max_encoding_message_size(max_message_size());
+    if gzip {
+        server = server
+            .accept_compressed(CompressionEncoding::Gzip)
+            .send_compressed(CompressionEncoding::Gzip);
+    }
+    builder
+        .add_service(InterceptedService::new(server, ServerAuth::new(tokens)))
+        .serve_with_incoming(incoming)
         .await?;
 
     Ok(())
@@ -0,0 +1,244 @@
+use crate::message::{message::Payload as Body, Chunk, Message};
+use crate::payload::{max_item_size, PayloadError, VERSION};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use tonic::{Status, Streaming};
+use uuid::Uuid;
+
+/// Size of a single chunk, messages not larger than this are sent whole.
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Chunked messages received at once, the oldest one is dropped when another starts.
+const MAX_TRANSFERS: usize = 4;
+
+/// Splits `message` into chunks if it's larger than `CHUNK_SIZE`.
+pub fn split(message: Message) -> Vec<Message> {
+    if prost::Message::encoded_len(&message) <= CHUNK_SIZE {
+        return vec![message];
+    }
+
+    let encoded = prost::Message::encode_to_vec(&message);
+    let sha256 = Sha256::digest(&encoded).to_vec();
+    let transfer_id = Uuid::new_v4().to_string();
+    let count = encoded.len().div_ceil(CHUNK_SIZE) as u32;
+    encoded
+        .chunks(CHUNK_SIZE)
+        .enumerate()
+        .map(|(index, data)| Message {
+            version: VERSION,
+            payload: Some(Body::Chunk(Chunk {
+                transfer_id: transfer_id.clone(),
+                index: index as u32,
+                count,
+                total_size: encoded.len() as u64,
+                sha256: sha256.clone(),
+                data: data.to_vec(),
+            })),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Puts chunked messages back together.
+#[derive(Default)]
+pub struct Reassembler {
+    transfers: HashMap<String, Transfer>,
+    started: u64,
+}
+
+struct Transfer {
+    header: Chunk,
+    parts: Vec<Option<Vec<u8>>>,
+    size: usize,
+    started: u64,
+}
+
+impl Reassembler {
+    /// Returns message once all of its chunks arrived, messages that weren't chunked are
+    /// returned right away.
+    pub fn push(&mut self, message: Message) -> Result<Option<Message>, PayloadError> {
+        let chunk = match message.payload {
+            Some(Body::Chunk(chunk)) => chunk,
+            _ => return Ok(Some(message)),
+        };
+        if chunk.total_size > max_item_size() as u64 {
+            return Err(PayloadError::TooLarge(chunk.total_size));
+        }
+        if chunk.count == 0
+            || chunk.index >= chunk.count
+            || chunk.count as u64 != chunk.total_size.div_ceil(CHUNK_SIZE as u64)
+        {
+            return Err(PayloadError::Corrupted);
+        }
+
+        if !self.transfers.contains_key(&chunk.transfer_id) {
+            if self.transfers.len() == MAX_TRANSFERS {
+                let oldest = self
+                    .transfers
+                    .iter()
+                    .min_by_key(|(_, transfer)| transfer.started)
+                    .map(|(id, _)| id.clone());
+                if let Some(oldest) = oldest {
+                    self.transfers.remove(&oldest);
+                }
+            }
+            self.started += 1;
+            let header = Chunk {
+                data: vec![],
+                ..chunk.clone()
+            };
+            self.transfers.insert(
+                chunk.transfer_id.clone(),
+                Transfer {
+                    parts: vec![None; chunk.count as usize],
+                    header,
+                    size: 0,
+                    started: self.started,
+                },
+            );
+        }
+
+        let transfer = self.transfers.get_mut(&chunk.transfer_id).unwrap();
+        let header = &transfer.header;
+        if chunk.count != header.count
+            || chunk.total_size != header.total_size
+            || chunk.sha256 != header.sha256
+            || transfer.parts[chunk.index as usize].is_some()
+            || (transfer.size + chunk.data.len()) as u64 > header.total_size
+        {
+            self.transfers.remove(&chunk.transfer_id);
+            return Err(PayloadError::Corrupted);
+        }
+        transfer.size += chunk.data.len();
+        transfer.parts[chunk.index as usize] = Some(chunk.data);
+        if transfer.parts.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+
+        let transfer = self.transfers.remove(&chunk.transfer_id).unwrap();
+        let encoded: Vec<u8> = transfer.parts.into_iter().flatten().flatten().collect();
+        if encoded.len() as u64 != transfer.header.total_size
+            || Sha256::digest(&encoded).as_slice() != transfer.header.sha256
+        {
+            return Err(PayloadError::Corrupted);
+        }
+        prost::Message::decode(encoded.as_slice())
+            .map(Some)
+            .map_err(|_| PayloadError::Corrupted)
+    }
+}
+
+/// Stream of messages with chunks already put together.
+pub struct Incoming {
+    stream: Streaming<Message>,
+    chunks: Reassembler,
+}
+
+impl Incoming {
+    pub fn new(stream: Streaming<Message>) -> Self {
+        Self {
+            stream,
+            chunks: Reassembler::default(),
+        }
+    }
+
+    /// Same as `Streaming::message`, invalid chunked messages are skipped.
+    pub async fn message(&mut self) -> Result<Option<Message>, Status> {
+        while let Some(message) = self.stream.message().await? {
+            match self.chunks.push(message) {
+                Ok(Some(message)) => return Ok(Some(message)),
+                Ok(None) => continue,
+                Err(e) => println!("invalid message: {}", e),
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Text;
+
+    /// Message split into three chunks.
+    fn large(text: char) -> Message {
+        Message {
+            version: VERSION,
+            payload: Some(Body::Text(Text {
+                text: text.to_string().repeat(2 * CHUNK_SIZE + 1),
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn chunk(message: &mut Message) -> &mut Chunk {
+        match &mut message.payload {
+            Some(Body::Chunk(chunk)) => chunk,
+            other => panic!("not a chunk: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn out_of_order_chunks_are_put_together() {
+        let message = large('a');
+        let mut chunks = split(message.clone());
+        assert_eq!(chunks.len(), 3);
+        chunks.swap(0, 2);
+
+        let mut reassembler = Reassembler::default();
+        let last = chunks.pop().unwrap();
+        for chunk in chunks {
+            assert_eq!(reassembler.push(chunk).unwrap(), None);
+        }
+        assert_eq!(reassembler.push(last).unwrap(), Some(message));
+    }
+
+    #[test]
+    fn modified_chunk_is_rejected() {
+        let mut chunks = split(large('a'));
+        chunk(&mut chunks[1]).data[0] ^= 1;
+
+        let mut reassembler = Reassembler::default();
+        let last = chunks.pop().unwrap();
+        for chunk in chunks {
+            reassembler.push(chunk).unwrap();
+        }
+        assert!(matches!(
+            reassembler.push(last),
+            Err(PayloadError::Corrupted)
+        ));
+    }
+
+    #[test]
+    fn count_must_match_total_size() {
+        let chunks = split(large('a'));
+        for count in [2, 4] {
+            let mut first = chunks[0].clone();
+            chunk(&mut first).count = count;
+            assert!(matches!(
+                Reassembler::default().push(first),
+                Err(PayloadError::Corrupted)
+            ));
+        }
+    }
+
+    #[test]
+    fn oldest_transfer_is_evicted() {
+        let mut transfers: Vec<_> = "abcde".chars().map(|c| split(large(c))).collect();
+        let mut reassembler = Reassembler::default();
+        for chunks in &mut transfers {
+            assert_eq!(reassembler.push(chunks.remove(0)).unwrap(), None);
+        }
+        assert_eq!(reassembler.transfers.len(), MAX_TRANSFERS);
+
+        // first chunk of the oldest one is gone with it
+        for chunk in transfers.remove(0) {
+            assert_eq!(reassembler.push(chunk).unwrap(), None);
+        }
+        let newest = transfers.pop().unwrap();
+        let last = newest
+            .into_iter()
+            .map(|chunk| reassembler.push(chunk).unwrap());
+        assert_eq!(last.last().flatten(), Some(large('e')));
+    }
+}
@@ -0,0 +1,216 @@
+use crate::message::ImageEncoding;
+use crate::payload::{max_item_size, PayloadError};
+use arboard::ImageData;
+use clap::ValueEnum;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageReader, Limits, RgbaImage};
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicI32, AtomicU8, Ordering};
+
+/// Default of `--image-quality`.
+pub const DEFAULT_IMAGE_QUALITY: u8 = 90;
+
+/// Compressed encodings this build decodes.
+pub const IMAGE_ENCODINGS: [ImageEncoding; 3] =
+    [ImageEncoding::Png, ImageEncoding::Webp, ImageEncoding::Jpeg];
+
+static IMAGE_ENCODING: AtomicI32 = AtomicI32::new(ImageEncoding::Png as i32);
+static IMAGE_QUALITY: AtomicU8 = AtomicU8::new(DEFAULT_IMAGE_QUALITY);
+
+/// How images are sent, peers that can't decode it receive raw pixels.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum ImageFormat {
+    /// Uncompressed RGBA pixels
+    Raw,
+    /// Lossless
+    #[default]
+    Png,
+    /// Lossless, usually smaller than PNG but slower
+    Webp,
+    /// Lossy, drops transparency
+    Jpeg,
+}
+
+impl From<ImageFormat> for ImageEncoding {
+    fn from(format: ImageFormat) -> Self {
+        match format {
+            ImageFormat::Raw => ImageEncoding::Raw,
+            ImageFormat::Png => ImageEncoding::Png,
+            ImageFormat::Webp => ImageEncoding::Webp,
+            ImageFormat::Jpeg => ImageEncoding::Jpeg,
+        }
+    }
+}
+
+/// Encoding images are sent with when the receiver decodes it.
+pub fn image_encoding() -> ImageEncoding {
+    ImageEncoding::try_from(IMAGE_ENCODING.load(Ordering::Relaxed)).unwrap_or_default()
+}
+
+/// `quality` only affects JPEG, from 1 to 100.
+pub fn set_image_format(format: ImageFormat, quality: u8) {
+    IMAGE_ENCODING.store(ImageEncoding::from(format).into(), Ordering::Relaxed);
+    IMAGE_QUALITY.store(quality, Ordering::Relaxed);
+}
+
+/// Compresses RGBA `image`, `None` if it should be sent raw.
+pub fn encode_image(image: &ImageData, encoding: ImageEncoding) -> Option<Vec<u8>> {
+    let pixels = RgbaImage::from_raw(
+        image.width as u32,
+        image.height as u32,
+        image.bytes.to_vec(),
+    )?;
+    let mut data = Vec::new();
+    let result = match encoding {
+        ImageEncoding::Raw => return None,
+        ImageEncoding::Png => pixels.write_with_encoder(PngEncoder::new(&mut data)),
+        ImageEncoding::Webp => pixels.write_with_encoder(WebPEncoder::new_lossless(&mut data)),
+        ImageEncoding::Jpeg => DynamicImage::ImageRgba8(pixels)
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(
+                &mut data,
+                IMAGE_QUALITY.load(Ordering::Relaxed),
+            )),
+    };
+    match result {
+        Ok(()) => Some(data),
+        Err(e) => {
+            println!("failed to compress image, sending it raw: {}", e);
+            None
+        }
+    }
+}
+
+/// Decodes image of `encoding` back to RGBA pixels of the announced size.
+pub fn decode_image(
+    width: u32,
+    height: u32,
+    encoding: i32,
+    data: Vec<u8>,
+) -> Result<ImageData<'static>, PayloadError> {
+    let malformed = |size| PayloadError::MalformedImage {
+        width,
+        height,
+        size,
+    };
+    let format = match ImageEncoding::try_from(encoding) {
+        Ok(ImageEncoding::Raw) => {
+            if rgba_size(width, height) != Some(data.len() as u64) {
+                return Err(malformed(data.len()));
+            }
+            return Ok(ImageData {
+                width: width as usize,
+                height: height as usize,
+                bytes: Cow::Owned(data),
+            });
+        }
+        Ok(ImageEncoding::Png) => image::ImageFormat::Png,
+        Ok(ImageEncoding::Webp) => image::ImageFormat::WebP,
+        Ok(ImageEncoding::Jpeg) => image::ImageFormat::Jpeg,
+        Err(_) => return Err(PayloadError::Unsupported("image encoding")),
+    };
+
+    // decoded pixels must fit the same limit as raw ones, decoder gets some room on top
+    let size = rgba_size(width, height).ok_or_else(|| malformed(data.len()))?;
+    if size > max_item_size() as u64 {
+        return Err(PayloadError::TooLarge(size));
+    }
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(width);
+    limits.max_image_height = Some(height);
+    limits.max_alloc = Some(max_item_size() as u64 * 2);
+    let mut reader = ImageReader::with_format(Cursor::new(&data), format);
+    reader.limits(limits);
+    let pixels = reader
+        .decode()
+        .map_err(|_| malformed(data.len()))?
+        .into_rgba8();
+    if pixels.width() != width || pixels.height() != height {
+        return Err(malformed(data.len()));
+    }
+    Ok(ImageData {
+        width: width as usize,
+        height: height as usize,
+        bytes: Cow::Owned(pixels.into_raw()),
+    })
+}
+
+/// Size of RGBA pixels of an image, `None` if it doesn't fit into `u64`.
+fn rgba_size(width: u32, height: u32) -> Option<u64> {
+    (width as u64).checked_mul(height as u64)?.checked_mul(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: usize, height: usize) -> ImageData<'static> {
+        let bytes = (0..width * height)
+            .flat_map(|i| [(i % 256) as u8, (i / 256) as u8, 128, 255])
+            .collect();
+        ImageData {
+            width,
+            height,
+            bytes: Cow::Owned(bytes),
+        }
+    }
+
+    fn encoded(image: &ImageData, encoding: ImageEncoding) -> Vec<u8> {
+        encode_image(image, encoding).expect("image was not compressed")
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        let image = gradient(64, 48);
+        for encoding in [ImageEncoding::Png, ImageEncoding::Webp] {
+            let data = encoded(&image, encoding);
+            let decoded = decode_image(64, 48, encoding.into(), data).unwrap();
+            assert_eq!(decoded.bytes, image.bytes, "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn jpeg_round_trip() {
+        let image = gradient(64, 48);
+        let data = encoded(&image, ImageEncoding::Jpeg);
+        let decoded = decode_image(64, 48, ImageEncoding::Jpeg.into(), data).unwrap();
+        assert_eq!((decoded.width, decoded.height), (64, 48));
+        assert_eq!(decoded.bytes.len(), image.bytes.len());
+        assert!(decoded.bytes.chunks(4).all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn raw_images_are_not_compressed() {
+        assert_eq!(encode_image(&gradient(4, 4), ImageEncoding::Raw), None);
+    }
+
+    #[test]
+    fn dimensions_must_match() {
+        let data = encoded(&gradient(64, 48), ImageEncoding::Png);
+        for (width, height) in [(64, 64), (48, 64), (32, 48)] {
+            assert!(matches!(
+                decode_image(width, height, ImageEncoding::Png.into(), data.clone()),
+                Err(PayloadError::MalformedImage { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn announced_size_bounds_decoding() {
+        // header of the image is larger than announced, decoder stops before allocating
+        let data = encoded(&gradient(1024, 1024), ImageEncoding::Png);
+        assert!(matches!(
+            decode_image(16, 16, ImageEncoding::Png.into(), data.clone()),
+            Err(PayloadError::MalformedImage { .. })
+        ));
+
+        // announced size alone is above the limit
+        assert!(matches!(
+            decode_image(100_000, 100_000, ImageEncoding::Png.into(), data),
+            Err(PayloadError::TooLarge(_))
+        ));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::message::{message::Payload as Body, Compressed, Compression, ImageEncoding, Message};
+use crate::payload::{content_kind, max_message_size, PayloadError};
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Payloads smaller than this are sent uncompressed.
+pub const COMPRESSION_THRESHOLD: usize = 4 * 1024;
+
+/// Compression codecs this build decodes.
+pub const COMPRESSIONS: [Compression; 2] = [Compression::Zstd, Compression::Gzip];
+
+const ZSTD_LEVEL: i32 = 3;
+
+static COMPRESSION: AtomicI32 = AtomicI32::new(Compression::Zstd as i32);
+
+/// How large payloads are compressed, peers that can't decode it receive them uncompressed.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum CompressionCodec {
+    None,
+    #[default]
+    Zstd,
+    Gzip,
+}
+
+impl From<CompressionCodec> for Compression {
+    fn from(codec: CompressionCodec) -> Self {
+        match codec {
+            CompressionCodec::None => Compression::None,
+            CompressionCodec::Zstd => Compression::Zstd,
+            CompressionCodec::Gzip => Compression::Gzip,
+        }
+    }
+}
+
+/// Codec payloads are compressed with when the receiver decodes it.
+pub fn compression() -> Compression {
+    Compression::try_from(COMPRESSION.load(Ordering::Relaxed)).unwrap_or_default()
+}
+
+pub fn set_compression(codec: CompressionCodec) {
+    COMPRESSION.store(Compression::from(codec).into(), Ordering::Relaxed);
+}
+
+/// Compresses payload of `message` with `codec` if it is large enough and gets smaller.
+pub fn compress(mut message: Message, codec: Compression) -> Message {
+    let compressible = match &message.payload {
+        Some(Body::Image(image)) => image.encoding() == ImageEncoding::Raw,
+        Some(Body::Sealed(_)) | Some(Body::Chunk(_)) | Some(Body::Compressed(_)) | None => false,
+        Some(_) => true,
+    };
+    let Some(kind) = content_kind(&message) else {
+        return message;
+    };
+    if !compressible || codec == Compression::None {
+        return message;
+    }
+
+    let inner = Message {
+        payload: message.payload.take(),
+        ..Default::default()
+    };
+    let plain = prost::Message::encode_to_vec(&inner);
+    let data = if plain.len() < COMPRESSION_THRESHOLD {
+        None
+    } else {
+        match codec {
+            Compression::None => None,
+            Compression::Zstd => zstd::bulk::compress(&plain, ZSTD_LEVEL).ok(),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder
+                    .write_all(&plain)
+                    .and_then(|_| encoder.finish())
+                    .ok()
+            }
+        }
+    };
+    message.payload = match data {
+        Some(data) if data.len() < plain.len() => Some(Body::Compressed(Compressed {
+            codec: codec.into(),
+            size: plain.len() as u64,
+            data,
+            kind: kind.into(),
+        })),
+        _ => inner.payload,
+    };
+    message
+}
+
+/// Restores `Message` carrying only the payload that was compressed.
+pub fn decompress(compressed: &Compressed) -> Result<Message, PayloadError> {
+    if compressed.size > max_message_size() as u64 {
+        return Err(PayloadError::TooLarge(compressed.size));
+    }
+    let size = compressed.size as usize;
+    let plain = match Compression::try_from(compressed.codec) {
+        Ok(Compression::Zstd) => {
+            zstd::bulk::decompress(&compressed.data, size).map_err(|_| PayloadError::Corrupted)?
+        }
+        Ok(Compression::Gzip) => {
+            let mut plain = Vec::with_capacity(size);
+            GzDecoder::new(compressed.data.as_slice())
+                .take(size as u64 + 1)
+                .read_to_end(&mut plain)
+                .map_err(|_| PayloadError::Corrupted)?;
+            plain
+        }
+        Ok(Compression::None) | Err(_) => return Err(PayloadError::Unsupported("compression")),
+    };
+    if plain.len() != size {
+        return Err(PayloadError::Corrupted);
+    }
+
+    let inner: Message =
+        prost::Message::decode(plain.as_slice()).map_err(|_| PayloadError::Corrupted)?;
+    let nested = matches!(
+        inner.payload,
+        Some(Body::Sealed(_)) | Some(Body::Chunk(_)) | Some(Body::Compressed(_))
+    );
+    if nested || content_kind(&inner) != Some(compressed.kind()) {
+        return Err(PayloadError::Corrupted);
+    }
+    Ok(inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{ContentKind, Text};
+
+    fn text(text: String) -> Message {
+        Message {
+            payload: Some(Body::Text(Text { text })),
+            ..Default::default()
+        }
+    }
+
+    fn compressed(message: &Message) -> Compressed {
+        match &message.payload {
+            Some(Body::Compressed(compressed)) => compressed.clone(),
+            other => panic!("not compressed: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let message = text("clipboard ".repeat(1000));
+        for codec in COMPRESSIONS {
+            let sent = compress(message.clone(), codec);
+            let compressed = compressed(&sent);
+            assert_eq!(compressed.codec(), codec);
+            assert!(compressed.data.len() < compressed.size as usize);
+            assert_eq!(decompress(&compressed).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn small_payloads_are_sent_as_is() {
+        let message = text("clipboard".to_owned());
+        assert_eq!(compress(message.clone(), Compression::Zstd), message);
+        let large = text("clipboard ".repeat(1000));
+        assert_eq!(compress(large.clone(), Compression::None), large);
+    }
+
+    #[test]
+    fn announced_size_is_limited() {
+        let mut compressed = compressed(&compress(
+            text("clipboard ".repeat(1000)),
+            Compression::Zstd,
+        ));
+        compressed.size = max_message_size() as u64 + 1;
+        assert!(matches!(
+            decompress(&compressed),
+            Err(PayloadError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn lying_size_is_rejected() {
+        for codec in COMPRESSIONS {
+            let original = compressed(&compress(text("clipboard ".repeat(1000)), codec));
+            for size in [original.size - 1, original.size + 1, 0] {
+                let lying = Compressed {
+                    size,
+                    ..original.clone()
+                };
+                assert!(
+                    matches!(decompress(&lying), Err(PayloadError::Corrupted)),
+                    "{:?} of size {}",
+                    codec,
+                    size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn kind_must_match_content() {
+        let mut compressed = compressed(&compress(
+            text("clipboard ".repeat(1000)),
+            Compression::Gzip,
+        ));
+        compressed.kind = ContentKind::Image.into();
+        assert!(matches!(
+            decompress(&compressed),
+            Err(PayloadError::Corrupted)
+        ));
+    }
+}
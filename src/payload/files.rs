@@ -0,0 +1,267 @@
+use crate::message::File;
+use crate::payload::{max_item_size, PayloadError};
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static INBOX: OnceLock<PathBuf> = OnceLock::new();
+
+/// Directory received files are stored in, `lanclip/inbox` in per-user data directory unless
+/// set.
+pub fn inbox() -> &'static Path {
+    INBOX.get_or_init(|| {
+        data_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("lanclip")
+            .join("inbox")
+    })
+}
+
+pub fn set_inbox(path: PathBuf) {
+    INBOX.set(path).ok();
+}
+
+/// Reads files copied from file manager, directories are skipped.
+pub fn read(paths: Vec<PathBuf>) -> Result<Vec<File>, PayloadError> {
+    let mut total = 0;
+    let mut found = Vec::new();
+    for path in paths {
+        let metadata = fs::metadata(&path)?;
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        if !metadata.is_file() {
+            println!("skipping {}, only files are synchronized", path.display());
+            continue;
+        }
+        total += metadata.len();
+        found.push((name.to_string_lossy().into_owned(), path));
+    }
+    // checked before reading, so huge files aren't loaded into memory
+    if total > max_item_size() as u64 {
+        return Err(PayloadError::TooLarge(total));
+    }
+
+    let mut files = Vec::with_capacity(found.len());
+    for (name, path) in found {
+        let content = fs::read(&path)?;
+        files.push(File {
+            name,
+            sha256: Sha256::digest(&content).to_vec(),
+            content,
+        });
+    }
+    Ok(files)
+}
+
+/// Stores received files in the inbox, nothing is stored unless every file is intact.
+pub fn save(files: Vec<File>) -> Result<Vec<PathBuf>, PayloadError> {
+    save_to(inbox(), files)
+}
+
+fn save_to(inbox: &Path, files: Vec<File>) -> Result<Vec<PathBuf>, PayloadError> {
+    let total: usize = files.iter().map(|file| file.content.len()).sum();
+    if total > max_item_size() {
+        return Err(PayloadError::TooLarge(total as u64));
+    }
+    for file in &files {
+        if file.sha256.is_empty() || Sha256::digest(&file.content).as_slice() != file.sha256 {
+            return Err(PayloadError::Corrupted);
+        }
+        // names come from the remote side, anything but a plain file name is refused
+        if Path::new(&file.name).file_name() != Some(OsStr::new(&file.name)) {
+            return Err(PayloadError::Corrupted);
+        }
+    }
+
+    create_private_dir(inbox)?;
+    let mut paths = Vec::with_capacity(files.len());
+    for file in files {
+        let (path, mut created) = create_unique(&inbox.join(&file.name))?;
+        created.write_all(&file.content)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// `text/uri-list` of `paths`, so file managers paste them as files.
+pub fn uri_list(paths: &[PathBuf]) -> String {
+    let mut uri_list = String::new();
+    for path in paths {
+        uri_list.push_str("file://");
+        for &byte in path.to_string_lossy().as_bytes() {
+            match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' => {
+                    uri_list.push(byte as char)
+                }
+                byte => uri_list.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+        uri_list.push_str("\r\n");
+    }
+    uri_list
+}
+
+/// Creates `path`, or `name (n).ext` next to it if it's taken. Existing files and symlinks
+/// are never opened.
+fn create_unique(path: &Path) -> io::Result<(PathBuf, fs::File)> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let mut candidate = path.to_path_buf();
+    let mut n = 1;
+    loop {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(file) => return Ok((candidate, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                candidate = path.with_file_name(format!("{} ({}){}", stem, n, extension));
+                n += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Creates `dir` accessible only by current user, refuses existing one others may write to.
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() || metadata.permissions().mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} must be a directory accessible only by its owner",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+/// Per-user data directory of the platform, `None` if it can't be told.
+fn data_dir() -> Option<PathBuf> {
+    let home = || std::env::var_os("HOME").map(PathBuf::from);
+    if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library").join("Application Support"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| home().map(|home| home.join(".local").join("share")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn file(name: &str, content: &str) -> File {
+        File {
+            name: name.to_owned(),
+            sha256: Sha256::digest(content).to_vec(),
+            content: content.as_bytes().to_vec(),
+        }
+    }
+
+    /// Empty directory removed once the test is done.
+    struct Inbox(PathBuf);
+
+    impl Inbox {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!("lanclip-test-{}", Uuid::new_v4())))
+        }
+
+        fn names(&self) -> Vec<String> {
+            let Ok(entries) = fs::read_dir(&self.0) else {
+                return vec![];
+            };
+            let mut names: Vec<_> = entries
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        }
+    }
+
+    impl Drop for Inbox {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn taken_names_get_suffix() {
+        let inbox = Inbox::new();
+        save_to(&inbox.0, vec![file("notes.txt", "first")]).unwrap();
+        let paths = save_to(&inbox.0, vec![file("notes.txt", "second")]).unwrap();
+
+        assert_eq!(paths, [inbox.0.join("notes (1).txt")]);
+        assert_eq!(fs::read_to_string(&paths[0]).unwrap(), "second");
+        assert_eq!(inbox.names(), ["notes (1).txt", "notes.txt"]);
+    }
+
+    #[test]
+    fn names_are_checked_before_writing() {
+        let inbox = Inbox::new();
+        for name in ["../x", "dir/x", "/etc/x", "..", ""] {
+            let files = vec![file("first.txt", "first"), file(name, "x")];
+            assert!(
+                matches!(save_to(&inbox.0, files), Err(PayloadError::Corrupted)),
+                "{}",
+                name
+            );
+        }
+        assert!(inbox.names().is_empty());
+    }
+
+    #[test]
+    fn modified_file_is_rejected() {
+        let inbox = Inbox::new();
+        let mut modified = file("second.txt", "second");
+        modified.content[0] ^= 1;
+        let files = vec![file("first.txt", "first"), modified];
+        assert!(matches!(
+            save_to(&inbox.0, files),
+            Err(PayloadError::Corrupted)
+        ));
+        assert!(inbox.names().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_inbox_is_refused() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let inbox = Inbox::new();
+        fs::create_dir(&inbox.0).unwrap();
+        fs::set_permissions(&inbox.0, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(matches!(
+            save_to(&inbox.0, vec![file("notes.txt", "notes")]),
+            Err(PayloadError::Io(_))
+        ));
+        assert!(inbox.names().is_empty());
+    }
+}
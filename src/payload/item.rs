@@ -0,0 +1,313 @@
+use crate::clipboard::{ClipboardType, Selection};
+use crate::message::{
+    self, message::Payload as Body, Compression, ContentKind, ImageEncoding, Message,
+};
+use crate::payload::{
+    codec, compression, content_kind, files, max_item_size, Encoding, Payload, PayloadError,
+    VERSION,
+};
+use arboard::{Clipboard, Set};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Clipboard types read on every change, from the richest one.
+const TYPES: [ClipboardType; 4] = [
+    ClipboardType::FILE,
+    ClipboardType::IMAGE,
+    ClipboardType::HTML,
+    ClipboardType::TEXT,
+];
+
+/// Targets plain text is offered as, MIME types first and then the older X11 ones.
+const TEXT_TARGETS: [&str; 5] = [
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "STRING",
+    "TEXT",
+];
+
+/// Everything a single copy offers, keyed by MIME type.
+#[derive(Debug, Clone, Default)]
+pub struct ClipboardItem {
+    representations: BTreeMap<&'static str, Payload>,
+}
+
+impl ClipboardItem {
+    /// Reads every representation available in `selection`, `None` if there is nothing to send.
+    ///
+    /// When all of them don't fit into `max_item_size` the largest ones are left out.
+    pub fn read(clipboard: &mut Clipboard, selection: Selection) -> Option<Self> {
+        let mut representations: Vec<_> = TYPES
+            .iter()
+            .filter_map(|r#type| Payload::read(clipboard, selection, r#type))
+            .map(|payload| (payload.size(), payload))
+            .collect();
+        representations.sort_by_key(|(size, _)| *size);
+        let mut total = 0;
+        let item: Self = representations
+            .into_iter()
+            .take_while(|(size, _)| {
+                total += size;
+                total <= max_item_size()
+            })
+            .map(|(_, payload)| payload)
+            .collect();
+
+        if item.representations.is_empty() {
+            None
+        } else {
+            Some(item)
+        }
+    }
+
+    /// Writes every representation where `selection` can offer several of them, the richest one
+    /// otherwise (HTML comes with its plain text).
+    pub fn write(self, selection: Selection, set: Set<'_>) -> Result<(), PayloadError> {
+        if selection.offers_targets() {
+            return Ok(selection.offer(self.targets()?, false)?);
+        }
+        self.into_richest().ok_or(PayloadError::Empty)?.write(set)
+    }
+
+    /// Content of every representation keyed by target it is offered as, from the richest one.
+    ///
+    /// Files are stored in the inbox and offered as a list of their paths, plain text falls back
+    /// to alternative text of the richer representations.
+    pub fn targets(self) -> Result<Vec<(&'static str, Vec<u8>)>, PayloadError> {
+        let mut payloads: Vec<_> = self.representations.into_values().collect();
+        payloads.sort_by_key(Payload::rank);
+
+        let mut targets = Vec::new();
+        let mut text = None;
+        let mut fallback = None;
+        for payload in payloads {
+            match payload {
+                Payload::Text(value) => text = Some(value),
+                Payload::Image(image) => {
+                    let png = codec::encode_image(&image, ImageEncoding::Png)
+                        .ok_or(PayloadError::Unsupported("image"))?;
+                    targets.push(("image/png", png));
+                }
+                Payload::Files(files) => {
+                    let paths = files::save(files)?;
+                    println!(
+                        "received {} file(s) into {}",
+                        paths.len(),
+                        files::inbox().display()
+                    );
+                    let uri_list = files::uri_list(&paths);
+                    let copied = format!("copy\n{}", uri_list.trim_end().replace("\r\n", "\n"));
+                    targets.push(("text/uri-list", uri_list.into_bytes()));
+                    targets.push(("x-special/gnome-copied-files", copied.into_bytes()));
+                    let paths: Vec<_> = paths.iter().map(|path| path.to_string_lossy()).collect();
+                    fallback.get_or_insert(paths.join("\n"));
+                }
+                Payload::Html { html, alt_text } => {
+                    targets.push(("text/html", html.into_bytes()));
+                    fallback.get_or_insert(alt_text);
+                }
+                Payload::Rtf { rtf, alt_text } => {
+                    targets.push(("text/rtf", rtf));
+                    fallback.get_or_insert(alt_text);
+                }
+            }
+        }
+        if let Some(text) = text.or(fallback).filter(|text| !text.is_empty()) {
+            targets.extend(TEXT_TARGETS.map(|target| (target, text.clone().into_bytes())));
+        }
+        Ok(targets)
+    }
+
+    /// Every representation keyed by MIME type.
+    #[cfg(test)]
+    pub fn representations(&self) -> &BTreeMap<&'static str, Payload> {
+        &self.representations
+    }
+
+    /// Builds message of this item, receivers that don't support items get the richest
+    /// representation only.
+    pub fn encode(self, selection: Selection, encoding: Encoding) -> Message {
+        if self.representations.len() == 1 || !encoding.items {
+            return match self.into_richest() {
+                Some(payload) => payload.encode(selection, encoding),
+                None => Message::default(),
+            };
+        }
+
+        // item is compressed as a whole
+        let plain = Encoding {
+            compression: Compression::None,
+            ..encoding
+        };
+        let representations = self
+            .representations
+            .into_values()
+            .map(|payload| Message {
+                payload: payload.encode(selection, plain).payload,
+                ..Default::default()
+            })
+            .collect();
+        let message = Message {
+            selection: message::Selection::from(selection).into(),
+            version: VERSION,
+            payload: Some(Body::Item(message::Item { representations })),
+            ..Default::default()
+        };
+        compression::compress(message, encoding.compression)
+    }
+
+    /// Decodes message of either an item or a single payload, representations this build
+    /// doesn't understand are skipped.
+    pub fn decode(mut message: Message) -> Result<(Selection, Self), PayloadError> {
+        let representations = match message.payload.take() {
+            Some(Body::Item(item)) => item.representations,
+            Some(Body::Compressed(compressed)) => {
+                let inner = compression::decompress(&compressed)?;
+                return Self::decode(Message {
+                    payload: inner.payload,
+                    ..message
+                });
+            }
+            payload => {
+                let (selection, payload) = Payload::decode(Message { payload, ..message })?;
+                return Ok((selection, Self::from(payload)));
+            }
+        };
+
+        let selection = Selection::from(message.selection());
+        let mut item = Self::default();
+        for representation in representations {
+            let decoded = Payload::decode(Message {
+                payload: representation.payload,
+                ..message.clone()
+            });
+            match decoded {
+                Ok((_, payload)) => {
+                    item.representations.insert(payload.mime_type(), payload);
+                }
+                Err(e) => println!("skipping representation: {}", e),
+            }
+        }
+        if item.representations.is_empty() {
+            return Err(PayloadError::Empty);
+        }
+        Ok((selection, item))
+    }
+
+    /// Representation pasted when the clipboard holds only one.
+    pub fn richest(&self) -> Option<&Payload> {
+        self.representations
+            .values()
+            .min_by_key(|payload| payload.rank())
+    }
+
+    /// Hash of the representation that gets pasted, same for content read back after writing it.
+    pub fn content_hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        match self.richest() {
+            Some(Payload::Text(text)) => hasher.update(text),
+            Some(Payload::Image(image)) => {
+                hasher.update((image.width as u64).to_le_bytes());
+                hasher.update((image.height as u64).to_le_bytes());
+                hasher.update(&image.bytes);
+            }
+            // names of stored files may get a suffix
+            Some(Payload::Files(files)) => {
+                for file in files {
+                    hasher.update(Sha256::digest(&file.content));
+                }
+            }
+            Some(Payload::Html { html, .. }) => hasher.update(html),
+            Some(Payload::Rtf { rtf, .. }) => hasher.update(rtf),
+            None => {}
+        }
+        let kind = self.richest().map(Payload::mime_type).unwrap_or_default();
+        hasher.update(kind);
+        hasher.finalize().to_vec()
+    }
+
+    fn into_richest(mut self) -> Option<Payload> {
+        let richest = self.richest()?.mime_type();
+        self.representations.remove(richest)
+    }
+}
+
+impl FromIterator<Payload> for ClipboardItem {
+    fn from_iter<I: IntoIterator<Item = Payload>>(payloads: I) -> Self {
+        Self {
+            representations: payloads
+                .into_iter()
+                .map(|payload| (payload.mime_type(), payload))
+                .collect(),
+        }
+    }
+}
+
+impl From<Payload> for ClipboardItem {
+    fn from(payload: Payload) -> Self {
+        Self {
+            representations: BTreeMap::from([(payload.mime_type(), payload)]),
+        }
+    }
+}
+
+/// Representation of `item` the peer supporting only `supported` kinds should get instead.
+pub fn richest(item: message::Item, supported: &[ContentKind]) -> Option<Body> {
+    item.representations
+        .into_iter()
+        .filter_map(|representation| representation.payload)
+        .filter(|body| {
+            let message = Message {
+                payload: Some(body.clone()),
+                ..Default::default()
+            };
+            content_kind(&message).is_some_and(|kind| supported.contains(&kind))
+        })
+        .min_by_key(|body| match body {
+            Body::Files(_) => 0,
+            Body::Image(_) => 1,
+            Body::Html(_) => 2,
+            Body::Rtf(_) => 3,
+            _ => 4,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn html(alt_text: &str) -> Payload {
+        Payload::Html {
+            html: "<i>html</i>".to_owned(),
+            alt_text: alt_text.to_owned(),
+        }
+    }
+
+    fn text_of(targets: &[(&str, Vec<u8>)]) -> Option<String> {
+        let (_, text) = targets
+            .iter()
+            .find(|(target, _)| *target == "UTF8_STRING")?;
+        Some(String::from_utf8(text.clone()).unwrap())
+    }
+
+    #[test]
+    fn every_representation_is_offered() {
+        let item = ClipboardItem::from_iter([Payload::Text("text".to_owned()), html("alt")]);
+        let targets = item.targets().unwrap();
+
+        let offered: Vec<_> = targets.iter().map(|(target, _)| *target).collect();
+        assert_eq!(offered[0], "text/html");
+        assert!(TEXT_TARGETS.iter().all(|target| offered.contains(target)));
+        assert_eq!(text_of(&targets).as_deref(), Some("text"));
+    }
+
+    #[test]
+    fn text_falls_back_to_alt_text() {
+        let targets = ClipboardItem::from(html("alt")).targets().unwrap();
+        assert_eq!(text_of(&targets).as_deref(), Some("alt"));
+
+        let targets = ClipboardItem::from(html("")).targets().unwrap();
+        assert_eq!(text_of(&targets), None);
+    }
+}
@@ -0,0 +1,19 @@
+mod chunk;
+mod codec;
+mod compression;
+mod files;
+mod item;
+#[allow(clippy::module_inception)]
+mod payload;
+
+pub use chunk::{split, Incoming};
+pub use codec::{
+    decode_image, set_image_format, ImageFormat, DEFAULT_IMAGE_QUALITY, IMAGE_ENCODINGS,
+};
+pub use compression::{compress, decompress, set_compression, CompressionCodec, COMPRESSIONS};
+pub use files::set_inbox;
+pub use item::{richest, ClipboardItem};
+pub use payload::{
+    content_kind, max_item_size, max_message_size, set_max_item_size, Encoding, Payload,
+    PayloadError, DEFAULT_MAX_ITEM_SIZE, VERSION,
+};
@@ -0,0 +1,472 @@
+use crate::clipboard::{ClipboardType, Selection};
+use crate::message::{
+    self, message::Payload as Body, Compression, ContentKind, ImageEncoding, Message,
+};
+use crate::payload::{codec, compression, files};
+use arboard::{Clipboard, ImageData, Set};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Version of `Message` schema produced by this build.
+pub const VERSION: u32 = 1;
+
+/// Default of `--max-item-size`, in MiB.
+pub const DEFAULT_MAX_ITEM_SIZE: usize = 64;
+
+/// Largest clipboard item that is sent or accepted, in bytes.
+static MAX_ITEM_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_ITEM_SIZE * 1024 * 1024);
+
+pub fn max_item_size() -> usize {
+    MAX_ITEM_SIZE.load(Ordering::Relaxed)
+}
+
+pub fn set_max_item_size(size: usize) {
+    MAX_ITEM_SIZE.store(size, Ordering::Relaxed);
+}
+
+/// Largest single gRPC message. Streamed items are chunked, but history entries are returned whole.
+pub fn max_message_size() -> usize {
+    max_item_size() + 64 * 1024
+}
+
+/// How content is encoded for a particular receiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Encoding {
+    pub image: ImageEncoding,
+    pub compression: Compression,
+    /// Receiver pastes HTML, only its plain text alternative is sent otherwise.
+    pub html: bool,
+    /// Receiver takes every representation of an item, only the richest one is sent otherwise.
+    pub items: bool,
+}
+
+impl Encoding {
+    /// Encoding configured on command line, for receivers that support everything.
+    pub fn preferred() -> Self {
+        Self {
+            image: codec::image_encoding(),
+            compression: compression::compression(),
+            html: true,
+            items: true,
+        }
+    }
+}
+
+/// Clipboard content carried by `Message`.
+#[derive(Debug, Clone)]
+pub enum Payload {
+    Text(String),
+    Image(ImageData<'static>),
+    Files(Vec<message::File>),
+    Html { html: String, alt_text: String },
+    Rtf { rtf: Vec<u8>, alt_text: String },
+}
+
+#[derive(Debug)]
+pub enum PayloadError {
+    /// Message was produced by incompatible version of schema.
+    UnsupportedVersion(u32),
+    /// Message carries no payload at all.
+    Empty,
+    /// Image data can't be decoded into its dimensions.
+    MalformedImage {
+        width: u32,
+        height: u32,
+        size: usize,
+    },
+    /// Files can't be read or stored.
+    Io(std::io::Error),
+    /// Payload can't be written into clipboard by this build.
+    Unsupported(&'static str),
+    /// Item is larger than `max_item_size`.
+    TooLarge(u64),
+    /// Chunks don't add up to the announced message.
+    Corrupted,
+    /// Payload is encrypted but no passphrase is configured.
+    Sealed,
+    /// Payload is not encrypted although passphrase is configured.
+    Unsealed,
+    /// Encrypted payload failed authentication, wrong passphrase or modified in transit.
+    Tampered,
+    Clipboard(arboard::Error),
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadError::UnsupportedVersion(version) => {
+                write!(f, "unsupported message version: {}", version)
+            }
+            PayloadError::Empty => write!(f, "message has no payload"),
+            PayloadError::MalformedImage {
+                width,
+                height,
+                size,
+            } => write!(f, "malformed {}x{} image of {} bytes", width, height, size),
+            PayloadError::Unsupported(kind) => write!(f, "not supported type: {}", kind),
+            PayloadError::TooLarge(size) => write!(f, "item is too large: {} bytes", size),
+            PayloadError::Corrupted => write!(f, "chunked message is corrupted"),
+            PayloadError::Sealed => write!(f, "encrypted message, passphrase is required"),
+            PayloadError::Unsealed => write!(f, "refused unencrypted message"),
+            PayloadError::Tampered => write!(f, "failed to decrypt message"),
+            PayloadError::Io(e) => write!(f, "file error: {}", e),
+            PayloadError::Clipboard(e) => write!(f, "clipboard error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+impl From<std::io::Error> for PayloadError {
+    fn from(e: std::io::Error) -> Self {
+        PayloadError::Io(e)
+    }
+}
+
+impl From<arboard::Error> for PayloadError {
+    fn from(e: arboard::Error) -> Self {
+        PayloadError::Clipboard(e)
+    }
+}
+
+/// Kind of content carried by `message`, encrypted one included.
+pub fn content_kind(message: &Message) -> Option<ContentKind> {
+    match message.payload.as_ref()? {
+        Body::Text(_) => Some(ContentKind::Text),
+        Body::Image(_) => Some(ContentKind::Image),
+        Body::Files(_) => Some(ContentKind::Files),
+        Body::Html(_) => Some(ContentKind::Html),
+        Body::Rtf(_) => Some(ContentKind::Rtf),
+        Body::Sealed(sealed) => Some(sealed.kind()),
+        Body::Compressed(compressed) => Some(compressed.kind()),
+        Body::Item(_) => Some(ContentKind::Item),
+        Body::Chunk(_) => None,
+    }
+}
+
+impl Payload {
+    /// MIME type of this representation.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Payload::Text(_) => "text/plain;charset=utf-8",
+            Payload::Image(_) => "image/png",
+            Payload::Files(_) => "text/uri-list",
+            Payload::Html { .. } => "text/html",
+            Payload::Rtf { .. } => "text/rtf",
+        }
+    }
+
+    /// Size counted against `max_item_size`, in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Payload::Text(text) => text.len(),
+            Payload::Image(image) => image.bytes.len(),
+            Payload::Files(files) => files.iter().map(|file| file.content.len()).sum(),
+            Payload::Html { html, alt_text } => html.len() + alt_text.len(),
+            Payload::Rtf { rtf, alt_text } => rtf.len() + alt_text.len(),
+        }
+    }
+
+    /// Order of representations from the richest one, pasted when the clipboard holds only one.
+    pub fn rank(&self) -> u8 {
+        match self {
+            Payload::Files(_) => 0,
+            Payload::Image(_) => 1,
+            Payload::Html { .. } => 2,
+            Payload::Rtf { .. } => 3,
+            Payload::Text(_) => 4,
+        }
+    }
+
+    /// Reads content of `type` from `selection`, `None` if there is nothing to send.
+    pub fn read(
+        clipboard: &mut Clipboard,
+        selection: Selection,
+        r#type: &ClipboardType,
+    ) -> Option<Self> {
+        let payload = match r#type {
+            ClipboardType::TEXT => Payload::Text(selection.get(clipboard)?.text().ok()?),
+            ClipboardType::IMAGE => Payload::Image(selection.get(clipboard)?.image().ok()?),
+            ClipboardType::HTML => {
+                let html = selection.get(clipboard)?.html().ok()?;
+                let alt_text = selection.get(clipboard)?.text().unwrap_or_default();
+                Payload::Html { html, alt_text }
+            }
+            ClipboardType::FILE => match files::read(selection.get(clipboard)?.file_list().ok()?) {
+                Ok(files) if !files.is_empty() => Payload::Files(files),
+                Ok(_) => return None,
+                Err(e) => {
+                    println!("{}", e);
+                    return None;
+                }
+            },
+            ClipboardType::UNKNOWN => return None,
+        };
+        let size = payload.size();
+        if size > max_item_size() {
+            println!("{}", PayloadError::TooLarge(size as u64));
+            return None;
+        }
+        Some(payload)
+    }
+
+    /// Writes content into clipboard. Files can't be pasted this way, they are only stored in
+    /// the inbox.
+    pub fn write(self, set: Set<'_>) -> Result<(), PayloadError> {
+        match self {
+            Payload::Text(text) => set.text(text)?,
+            Payload::Image(image) => set.image(image)?,
+            Payload::Files(files) => {
+                let paths = files::save(files)?;
+                println!(
+                    "received {} file(s) into {}",
+                    paths.len(),
+                    files::inbox().display()
+                );
+                return Err(PayloadError::Unsupported("files"));
+            }
+            Payload::Html { html, alt_text } => set.html(html, Some(alt_text))?,
+            Payload::Rtf { .. } => return Err(PayloadError::Unsupported("rtf")),
+        }
+        Ok(())
+    }
+
+    /// Builds message of this content, images and large payloads are compressed as `encoding` says.
+    pub fn encode(self, selection: Selection, encoding: Encoding) -> Message {
+        let body = match self {
+            Payload::Text(text) => Body::Text(message::Text { text }),
+            Payload::Image(image) => {
+                let (encoding, data) = match codec::encode_image(&image, encoding.image) {
+                    Some(data) => (encoding.image, data),
+                    None => (ImageEncoding::Raw, image.bytes.into_owned()),
+                };
+                Body::Image(message::Image {
+                    width: image.width as u32,
+                    height: image.height as u32,
+                    format: message::PixelFormat::Rgba8.into(),
+                    encoding: encoding.into(),
+                    data,
+                })
+            }
+            Payload::Files(files) => Body::Files(message::Files { files }),
+            Payload::Html { alt_text, .. } if !encoding.html => {
+                Body::Text(message::Text { text: alt_text })
+            }
+            Payload::Html { html, alt_text } => Body::Html(message::Html { html, alt_text }),
+            Payload::Rtf { rtf, alt_text } => Body::Rtf(message::Rtf { rtf, alt_text }),
+        };
+
+        let message = Message {
+            selection: message::Selection::from(selection).into(),
+            version: VERSION,
+            payload: Some(body),
+            ..Default::default()
+        };
+        compression::compress(message, encoding.compression)
+    }
+
+    pub fn decode(message: Message) -> Result<(Selection, Self), PayloadError> {
+        if message.version != VERSION {
+            return Err(PayloadError::UnsupportedVersion(message.version));
+        }
+
+        let selection = Selection::from(message.selection());
+        let payload = match message.payload.ok_or(PayloadError::Empty)? {
+            Body::Compressed(compressed) => {
+                let inner = compression::decompress(&compressed)?;
+                return Self::decode(Message {
+                    payload: inner.payload,
+                    ..message
+                });
+            }
+            Body::Text(text) => Payload::Text(text.text),
+            Body::Image(image) => Payload::Image(codec::decode_image(
+                image.width,
+                image.height,
+                image.encoding,
+                image.data,
+            )?),
+            Body::Files(files) => Payload::Files(files.files),
+            Body::Html(html) => Payload::Html {
+                html: html.html,
+                alt_text: html.alt_text,
+            },
+            Body::Rtf(rtf) => Payload::Rtf {
+                rtf: rtf.rtf,
+                alt_text: rtf.alt_text,
+            },
+            Body::Sealed(_) => return Err(PayloadError::Sealed),
+            Body::Chunk(_) | Body::Item(_) => return Err(PayloadError::Corrupted),
+        };
+        Ok((selection, payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    const PLAIN: Encoding = Encoding {
+        image: ImageEncoding::Raw,
+        compression: Compression::None,
+        html: true,
+        items: true,
+    };
+
+    fn round_trip(payload: Payload, encoding: Encoding) -> Payload {
+        let message = payload.encode(Selection::Primary, encoding);
+        let (selection, payload) = Payload::decode(message).unwrap();
+        assert!(matches!(selection, Selection::Primary));
+        payload
+    }
+
+    fn image(width: u32, height: u32, encoding: i32, data: Vec<u8>) -> Message {
+        Message {
+            version: VERSION,
+            payload: Some(Body::Image(message::Image {
+                width,
+                height,
+                format: message::PixelFormat::Rgba8.into(),
+                encoding,
+                data,
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let text = "clipboard ".repeat(1000);
+        for compression in [Compression::None, Compression::Zstd, Compression::Gzip] {
+            let encoding = Encoding {
+                compression,
+                ..PLAIN
+            };
+            match round_trip(Payload::Text(text.clone()), encoding) {
+                Payload::Text(received) => assert_eq!(received, text),
+                other => panic!("unexpected payload: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn html_round_trip() {
+        let html = Payload::Html {
+            html: "<b>bold</b>".to_owned(),
+            alt_text: "bold".to_owned(),
+        };
+        match round_trip(html.clone(), PLAIN) {
+            Payload::Html { html, alt_text } => {
+                assert_eq!(html, "<b>bold</b>");
+                assert_eq!(alt_text, "bold");
+            }
+            other => panic!("unexpected payload: {:?}", other),
+        }
+
+        let without_html = Encoding {
+            html: false,
+            ..PLAIN
+        };
+        match round_trip(html, without_html) {
+            Payload::Text(text) => assert_eq!(text, "bold"),
+            other => panic!("unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn raw_image_round_trip() {
+        let bytes: Vec<u8> = (0..2 * 3 * 4).collect();
+        let image = ImageData {
+            width: 2,
+            height: 3,
+            bytes: Cow::Owned(bytes.clone()),
+        };
+        match round_trip(Payload::Image(image), PLAIN) {
+            Payload::Image(image) => {
+                assert_eq!((image.width, image.height), (2, 3));
+                assert_eq!(image.bytes.into_owned(), bytes);
+            }
+            other => panic!("unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn files_and_rtf_round_trip() {
+        let file = message::File {
+            name: "notes.txt".to_owned(),
+            content: b"notes".to_vec(),
+            sha256: vec![],
+        };
+        match round_trip(Payload::Files(vec![file.clone()]), PLAIN) {
+            Payload::Files(files) => assert_eq!(files, vec![file]),
+            other => panic!("unexpected payload: {:?}", other),
+        }
+
+        let rtf = Payload::Rtf {
+            rtf: b"{\\rtf1 text}".to_vec(),
+            alt_text: "text".to_owned(),
+        };
+        match round_trip(rtf, PLAIN) {
+            Payload::Rtf { rtf, alt_text } => {
+                assert_eq!(rtf, b"{\\rtf1 text}");
+                assert_eq!(alt_text, "text");
+            }
+            other => panic!("unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn image_of_wrong_size_is_malformed() {
+        let raw = ImageEncoding::Raw as i32;
+        let short = image(2, 3, raw, vec![0; 2 * 3 * 4 - 1]);
+        assert!(matches!(
+            Payload::decode(short),
+            Err(PayloadError::MalformedImage { size: 23, .. })
+        ));
+
+        // pixel count overflows, must not panic
+        let huge = image(u32::MAX, u32::MAX, raw, vec![0; 4]);
+        assert!(matches!(
+            Payload::decode(huge),
+            Err(PayloadError::MalformedImage { .. })
+        ));
+        let png = image(u32::MAX, u32::MAX, ImageEncoding::Png as i32, vec![0; 4]);
+        assert!(matches!(
+            Payload::decode(png),
+            Err(PayloadError::MalformedImage { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_messages_are_refused() {
+        let text = Payload::Text("text".to_owned()).encode(Selection::Clipboard, PLAIN);
+        let future = Message {
+            version: VERSION + 1,
+            ..text
+        };
+        assert!(matches!(
+            Payload::decode(future),
+            Err(PayloadError::UnsupportedVersion(_))
+        ));
+
+        let empty = Message {
+            version: VERSION,
+            ..Default::default()
+        };
+        assert!(matches!(Payload::decode(empty), Err(PayloadError::Empty)));
+
+        let sealed = Message {
+            version: VERSION,
+            payload: Some(Body::Sealed(message::Sealed::default())),
+            ..Default::default()
+        };
+        assert!(matches!(Payload::decode(sealed), Err(PayloadError::Sealed)));
+
+        let unknown_encoding = image(1, 1, 100, vec![0; 4]);
+        assert!(matches!(
+            Payload::decode(unknown_encoding),
+            Err(PayloadError::Unsupported(_))
+        ));
+    }
+}
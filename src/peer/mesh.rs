@@ -0,0 +1,98 @@
+use crate::echo::Origin;
+use crate::listener::{Follower, Queue};
+use crate::message::Message;
+use crate::server::registry::Registry;
+use crate::server::relay::Relay;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::Sender;
+
+/// Links of a serverless node, both accepted and dialed ones.
+///
+/// Every message is stamped with origin node and its sequence number and flooded to all links,
+/// nodes forward and apply only messages newer than the last one seen from the same origin,
+/// so change reaches every node exactly once whatever the topology.
+#[derive(Clone)]
+pub struct Mesh {
+    origin: Origin,
+    seen: Arc<Mutex<HashMap<String, u64>>>,
+    links: Relay,
+    local: Sender<Message>,
+    queue: Queue,
+}
+
+impl Mesh {
+    /// `local` receives messages that should be applied to local clipboard, `queue` applies
+    /// to every link.
+    pub fn new(local: Sender<Message>, queue: Queue) -> Self {
+        Self {
+            origin: Origin::default(),
+            seen: Arc::new(Mutex::new(HashMap::new())),
+            links: Relay::default(),
+            local,
+            queue,
+        }
+    }
+
+    pub fn origin(&self) -> &str {
+        self.origin.id()
+    }
+
+    pub fn queue(&self) -> Queue {
+        self.queue
+    }
+
+    pub fn add(&self, link: Follower) {
+        self.links.registry().join(link);
+    }
+
+    /// Removes link `id` once it is closed.
+    pub fn remove(&self, id: &str) {
+        self.links.registry().leave(id);
+    }
+
+    pub fn registry(&self) -> &Registry {
+        self.links.registry()
+    }
+
+    /// Marks `message` as the next local change, before it is sealed.
+    pub fn stamp(&self, message: Message) -> Message {
+        self.origin.stamp(message)
+    }
+
+    /// Sends stamped local change to every link.
+    pub async fn publish(&self, message: Message) {
+        self.links.publish(message);
+    }
+
+    /// Handles message received on link `from`.
+    pub async fn receive(&self, from: &str, mut message: Message) {
+        // client of older version connected to this node, its content enters mesh here
+        if message.origin.is_empty() {
+            message = self.origin.stamp(message);
+        } else if !self.is_new(&message) {
+            return;
+        }
+        self.links.broadcast(from, message.clone());
+        self.local.send(message).await.ok();
+    }
+
+    fn is_new(&self, message: &Message) -> bool {
+        if message.origin == self.origin.id() {
+            return false;
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        match seen.get_mut(&message.origin) {
+            Some(last) if *last >= message.sequence => false,
+            Some(last) => {
+                *last = message.sequence;
+                true
+            }
+            None => {
+                seen.insert(message.origin.clone(), message.sequence);
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,6 @@
+mod mesh;
+#[allow(clippy::module_inception)]
+mod peer;
+
+pub use mesh::Mesh;
+pub use peer::Peer;
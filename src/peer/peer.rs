@@ -0,0 +1,209 @@
+use crate::client::backoff::Backoff;
+use crate::client::client::{Change, Handler};
+use crate::client::{Endpoint, Target};
+use crate::clipboard::{ClipboardBackend, ClipboardType, Selection, SelectionMode};
+use crate::crypto::Cipher;
+use crate::discovery;
+use crate::echo::Synced;
+use crate::handshake::{self, SESSION_HEADER};
+use crate::listener::Follower;
+use crate::message::Message;
+use crate::payload::{ClipboardItem, Encoding, Incoming};
+use crate::peer::Mesh;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
+use tonic::codegen::tokio_stream::StreamExt;
+
+/// How often local network is searched for new nodes.
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(10);
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Node of serverless mesh, syncs local clipboard with every linked node.
+pub struct Peer {
+    mesh: Mesh,
+    incoming: Receiver<Message>,
+    clipboard: Arc<dyn ClipboardBackend>,
+    selections: SelectionMode,
+    cipher: Cipher,
+}
+
+impl Peer {
+    /// `incoming` is the receiving end of channel `mesh` was created with.
+    pub fn new(
+        mesh: Mesh,
+        incoming: Receiver<Message>,
+        clipboard: Arc<dyn ClipboardBackend>,
+        selections: SelectionMode,
+        cipher: Cipher,
+    ) -> Self {
+        Self {
+            mesh,
+            incoming,
+            clipboard,
+            selections,
+            cipher,
+        }
+    }
+
+    /// Keeps link to node at `endpoint` for as long as this node runs.
+    pub fn dial(&self, endpoint: Endpoint) {
+        let mesh = self.mesh.clone();
+        tokio::spawn(async move {
+            let mut backoff = Backoff::default();
+            loop {
+                if let Err(e) = link(&mesh, &endpoint, &mut backoff).await {
+                    println!("failed to link to {}: {}", endpoint.target, e);
+                }
+                tokio::time::sleep(backoff.next_delay()).await;
+            }
+        });
+    }
+
+    /// Periodically links to nodes found on local network, `template` tells how to talk to them.
+    ///
+    /// Of every pair of nodes only the one with lower id dials, so they end up with single link.
+    pub fn discover(&self, discovery_port: u16, template: Endpoint) {
+        let mesh = self.mesh.clone();
+        let linked = Arc::new(Mutex::new(HashSet::new()));
+        tokio::spawn(async move {
+            loop {
+                let servers = discovery::discover(discovery_port, DISCOVERY_TIMEOUT)
+                    .await
+                    .unwrap_or_default();
+                for server in servers {
+                    let instance_id = server.announcement.instance_id;
+                    if !server.announcement.peer
+                        || *instance_id <= *mesh.origin()
+                        || !linked.lock().unwrap().insert(instance_id.clone())
+                    {
+                        continue;
+                    }
+
+                    let endpoint = Endpoint {
+                        target: Target::Address(server.addr.to_string()),
+                        ..template.clone()
+                    };
+                    let mesh = mesh.clone();
+                    let linked = linked.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = link(&mesh, &endpoint, &mut Backoff::default()).await {
+                            println!("failed to link to {}: {}", endpoint.target, e);
+                        }
+                        // found again by the next search, possibly on new address
+                        linked.lock().unwrap().remove(&instance_id);
+                    });
+                }
+                tokio::time::sleep(DISCOVERY_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Publishes local changes and applies content received from mesh.
+    pub async fn start(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let (sender, mut changes) = tokio::sync::mpsc::channel::<Change>(16);
+        let selections = self.selections;
+        let clipboard = self.clipboard.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = clipboard.watch(Box::new(Handler::new(sender, selections))) {
+                eprintln!("clipboard watcher stopped: {}", e);
+            }
+        });
+
+        // content written from mesh must not be published again
+        let synced = Synced::default();
+        let own = self.mesh.origin().to_owned();
+        loop {
+            tokio::select! {
+                change = changes.recv() => {
+                    let Some((selection, clipboard_type)) = change else {
+                        return Ok(());
+                    };
+                    if matches!(clipboard_type, ClipboardType::UNKNOWN) {
+                        continue;
+                    }
+                    let Some(item) = self.clipboard.get(selection) else {
+                        continue;
+                    };
+                    let content_hash = item.content_hash();
+                    if synced.changed(selection, content_hash.clone(), &own).as_ref() != Some(&own) {
+                        continue;
+                    }
+                    let message = self.mesh.stamp(Message {
+                        content_hash,
+                        ..item.encode(selection, Encoding::preferred())
+                    });
+                    match self.cipher.seal(message) {
+                        Ok(message) => self.mesh.publish(message).await,
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                Some(message) = self.incoming.recv() => {
+                    let selection = Selection::from(message.selection());
+                    if !message.content_hash.is_empty()
+                        && synced.is_current(selection, &message.content_hash)
+                    {
+                        continue;
+                    }
+                    let origin = message.origin.clone();
+                    let (selection, item) =
+                        match self.cipher.open(message).and_then(ClipboardItem::decode) {
+                            Ok(decoded) => decoded,
+                            Err(e) => {
+                                println!("invalid message: {}", e);
+                                continue;
+                            }
+                        };
+                    if !selections.contains(selection) || !self.clipboard.supports(selection) {
+                        continue;
+                    }
+
+                    if !synced.received(selection, item.content_hash(), &origin) {
+                        continue;
+                    }
+                    if let Err(e) = self.clipboard.set(selection, item) {
+                        synced.forget(selection);
+                        println!("{}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Dials node at `endpoint` and relays its messages into mesh until connection is closed.
+async fn link(
+    mesh: &Mesh,
+    endpoint: &Endpoint,
+    backoff: &mut Backoff,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let host = endpoint.resolve().await?;
+    let mut client = endpoint.connect(&host).await?;
+    let welcome = client.handshake(handshake::hello()).await?.into_inner();
+    let server = welcome.server.ok_or("peer did not describe itself")?;
+
+    let (tx, rx) = tokio::sync::mpsc::channel(32);
+    let follower = Follower::new(tx, &server, host.parse().ok(), mesh.queue());
+    let id = follower.id().clone();
+
+    let mut request = tonic::Request::new(ReceiverStream::new(rx).filter_map(Result::ok));
+    request
+        .metadata_mut()
+        .insert(SESSION_HEADER, welcome.session_id.parse()?);
+    let mut stream = Incoming::new(client.changed(request).await?.into_inner());
+    println!(
+        "linked to peer: {} ({}, {})",
+        host, server.hostname, server.os
+    );
+    mesh.add(follower);
+    backoff.reset();
+
+    while let Ok(Some(msg)) = stream.message().await {
+        mesh.receive(&id, msg).await;
+    }
+    mesh.remove(&id);
+    println!("link closed: {}", host);
+    Ok(())
+}
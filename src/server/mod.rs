@@ -1 +1,4 @@
-pub mod server;
\ No newline at end of file
+pub mod registry;
+pub mod relay;
+#[allow(clippy::module_inception)]
+pub mod server;
@@ -0,0 +1,109 @@
+use crate::clock;
+use crate::listener::{Connection, Follower};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// Events kept for every subscriber, the slow one misses the oldest.
+const EVENTS: usize = 64;
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    Joined(Connection),
+    /// Counters of the connection are final
+    Left(Connection),
+}
+
+/// Followers connected to the server.
+///
+/// Follower is removed as soon as its stream ends, or once it stops accepting content, and
+/// every subscriber learns about it.
+#[derive(Clone)]
+pub struct Registry {
+    followers: Arc<Mutex<Vec<Follower>>>,
+    events: broadcast::Sender<Event>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self {
+            followers: Arc::default(),
+            events: broadcast::channel(EVENTS).0,
+        }
+    }
+}
+
+impl Registry {
+    pub fn join(&self, follower: Follower) {
+        let connection = follower.connection();
+        self.followers.lock().unwrap().push(follower);
+        self.events.send(Event::Joined(connection)).ok();
+    }
+
+    /// Removes follower `id` whose stream has ended.
+    pub fn leave(&self, id: &str) {
+        let mut followers = self.followers.lock().unwrap();
+        let Some(index) = followers.iter().position(|f| f.id() == id) else {
+            return;
+        };
+        let follower = followers.remove(index);
+        drop(followers);
+        self.events.send(Event::Left(follower.connection())).ok();
+    }
+
+    /// Calls `f` with every follower, the ones that stopped are removed afterwards.
+    pub fn for_each(&self, mut f: impl FnMut(&mut Follower)) {
+        let mut followers = self.followers.lock().unwrap();
+        followers.iter_mut().for_each(&mut f);
+
+        let mut stopped = vec![];
+        followers.retain(|follower| {
+            let working = follower.is_working();
+            if !working {
+                stopped.push(follower.connection());
+            }
+            working
+        });
+        drop(followers);
+        for connection in stopped {
+            self.events.send(Event::Left(connection)).ok();
+        }
+    }
+
+    /// Channel follower `id` joined, `None` if it is not connected.
+    pub fn channel_of(&self, id: &str) -> Option<String> {
+        let followers = self.followers.lock().unwrap();
+        followers
+            .iter()
+            .find(|follower| follower.id() == id)
+            .map(|follower| follower.channel().to_owned())
+    }
+
+    #[cfg(test)]
+    pub fn connections(&self) -> Vec<Connection> {
+        let followers = self.followers.lock().unwrap();
+        followers.iter().map(Follower::connection).collect()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+}
+
+/// Prints every connection that joins or leaves until the registry is gone.
+pub async fn log(mut events: broadcast::Receiver<Event>) {
+    loop {
+        match events.recv().await {
+            Ok(Event::Joined(connection)) => println!("new connection received: {}", connection),
+            Ok(Event::Left(connection)) => println!(
+                "connection closed: {}, {} after {}s",
+                connection,
+                connection.stats,
+                (clock::now() - connection.connected_since) / 1000
+            ),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                println!("missed {} connection events", missed)
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
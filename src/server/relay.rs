@@ -0,0 +1,38 @@
+use crate::message::Message;
+use crate::server::registry::Registry;
+
+/// Fans out messages between connected followers without touching any system clipboard.
+#[derive(Clone, Default)]
+pub struct Relay {
+    followers: Registry,
+}
+
+impl Relay {
+    pub fn new(followers: Registry) -> Self {
+        Self { followers }
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.followers
+    }
+
+    /// Forwards `message` to every other working follower in channel of its origin `from`.
+    ///
+    /// Nothing is sent once `from` is gone, its channel can't be told anymore.
+    pub fn broadcast(&self, from: &str, message: Message) {
+        let Some(channel) = self.followers.channel_of(from) else {
+            return;
+        };
+        self.followers.for_each(|follower| {
+            if follower.id() != from && follower.channel() == channel {
+                follower.send(Ok(message.clone()));
+            }
+        });
+    }
+
+    /// Sends `message` that doesn't come from any follower to all of them.
+    pub fn publish(&self, message: Message) {
+        self.followers
+            .for_each(|follower| follower.send(Ok(message.clone())));
+    }
+}
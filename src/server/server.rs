@@ -1,24 +1,230 @@
-use crate::listener::Follower;
-use crate::message;
-use arboard::{Clipboard, ImageData};
-use std::borrow::Cow;
+use crate::auth;
+use crate::clipboard::{ClipboardBackend, Selection, SelectionMode};
+use crate::config::ServerConfig;
+use crate::crypto::Cipher;
+use crate::echo::Synced;
+use crate::handshake::{self, Sessions, DEFAULT_CHANNEL};
+use crate::history::{History, Latest, Recorder};
+use crate::listener::{Follower, Queue};
+use crate::message::{self, Message};
+use crate::payload::{ClipboardItem, Incoming};
+use crate::peer::Mesh;
+use crate::server::registry::Registry;
+use crate::server::relay::Relay;
+use std::sync::Arc;
 use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status, Streaming};
 
 #[derive(Clone)]
 pub struct ClipboardServiceImpl {
-    sender: std::sync::mpsc::Sender<String>,
-    follower_sender: std::sync::mpsc::Sender<Follower>,
+    backend: Backend,
+    sessions: Sessions,
+    config: Arc<ServerConfig>,
+    history: History,
+    latest: Latest,
+    late_join: LateJoin,
+    queue: Queue,
+}
+
+/// What peer joining a channel receives before the next change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LateJoin {
+    /// Nothing
+    Off,
+    /// The last content seen in the channel
+    Push,
+    /// The last content seen in the channel if peer hasn't received it, unless peer changed its
+    /// clipboard while disconnected
+    #[default]
+    PushIfNewer,
+}
+
+/// Entries returned by `ListHistory` when request doesn't limit them.
+const HISTORY_PAGE: usize = 20;
+
+#[derive(Clone)]
+enum Backend {
+    /// Incoming content is applied to local clipboard, `ClipboardListener` fans it out.
+    /// Followers of other channels than `channel` are relayed between themselves.
+    Clipboard {
+        followers: Registry,
+        clipboard: Arc<dyn ClipboardBackend>,
+        synced: Synced,
+        selections: SelectionMode,
+        cipher: Cipher,
+        channel: String,
+        others: Relay,
+    },
+    /// Incoming content is forwarded to other followers as is.
+    Relay(Relay),
+    /// Node of serverless mesh, accepted streams are links to other nodes.
+    Mesh(Mesh),
 }
 
 impl ClipboardServiceImpl {
+    /// `followers` is shared with `ClipboardListener`.
     pub fn new(
-        sender: std::sync::mpsc::Sender<String>,
-        follower_sender: std::sync::mpsc::Sender<Follower>,
+        followers: Registry,
+        clipboard: Arc<dyn ClipboardBackend>,
+        synced: Synced,
+        selections: SelectionMode,
+        cipher: Cipher,
+        channel: String,
     ) -> Self {
         Self {
-            sender,
-            follower_sender,
+            backend: Backend::Clipboard {
+                others: Relay::new(followers.clone()),
+                followers,
+                clipboard,
+                synced,
+                selections,
+                cipher,
+                channel,
+            },
+            sessions: Sessions::default(),
+            config: Arc::default(),
+            history: History::default(),
+            latest: Latest::default(),
+            late_join: LateJoin::default(),
+            queue: Queue::default(),
+        }
+    }
+
+    /// Creates headless service that never touches a system clipboard.
+    pub fn relay() -> Self {
+        Self {
+            backend: Backend::Relay(Relay::default()),
+            sessions: Sessions::default(),
+            config: Arc::default(),
+            history: History::default(),
+            latest: Latest::default(),
+            late_join: LateJoin::default(),
+            queue: Queue::default(),
+        }
+    }
+
+    /// Creates service accepting links of other mesh nodes.
+    pub fn mesh(mesh: Mesh) -> Self {
+        Self {
+            queue: mesh.queue(),
+            backend: Backend::Mesh(mesh),
+            sessions: Sessions::default(),
+            config: Arc::default(),
+            history: History::default(),
+            latest: Latest::default(),
+            late_join: LateJoin::default(),
+        }
+    }
+
+    /// Restricts channels peers may join.
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        self.config = Arc::new(config);
+        self
+    }
+
+    /// Keeps content passing through the server in `history`.
+    pub fn with_history(mut self, history: History) -> Self {
+        self.history = history;
+        self
+    }
+
+    pub fn with_late_join(mut self, late_join: LateJoin) -> Self {
+        self.late_join = late_join;
+        self
+    }
+
+    /// How content waits for followers that don't keep up.
+    pub fn with_queue(mut self, queue: Queue) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    /// Followers of every channel.
+    pub fn registry(&self) -> Registry {
+        match &self.backend {
+            Backend::Clipboard { followers, .. } => followers.clone(),
+            Backend::Relay(relay) => relay.registry().clone(),
+            Backend::Mesh(mesh) => mesh.registry().clone(),
+        }
+    }
+
+    /// Last content of every channel, shared with `ClipboardListener`.
+    pub fn latest(&self) -> Latest {
+        self.latest.clone()
+    }
+
+    /// Content to push to the peer joining `channel`.
+    fn late_join_item(&self, channel: &str, hello: &message::Hello) -> Option<message::Message> {
+        match self.late_join {
+            LateJoin::Off => None,
+            LateJoin::Push => self.latest.newer_than(channel, 0),
+            LateJoin::PushIfNewer if hello.changed_offline => None,
+            LateJoin::PushIfNewer => self.latest.newer_than(channel, hello.last_revision),
+        }
+    }
+
+    /// Checks that caller may access `channel`, returns its actual name.
+    #[allow(clippy::result_large_err)]
+    fn admit<'a, T>(&self, request: &Request<T>, channel: &'a str) -> Result<&'a str, Status> {
+        let channel = if channel.is_empty() {
+            DEFAULT_CHANNEL
+        } else {
+            channel
+        };
+        self.config.admit(channel, auth::bearer(request))?;
+        Ok(channel)
+    }
+
+    async fn apply(
+        mut stream: Incoming,
+        id: String,
+        clipboard: Arc<dyn ClipboardBackend>,
+        synced: Synced,
+        selections: SelectionMode,
+        cipher: Cipher,
+        recorder: Recorder,
+    ) {
+        while let Ok(Some(msg)) = stream.message().await {
+            // content clipboard already holds
+            let selection = Selection::from(msg.selection());
+            if !msg.content_hash.is_empty() && synced.is_current(selection, &msg.content_hash) {
+                continue;
+            }
+            let (selection, item) = match cipher.open(msg.clone()).and_then(ClipboardItem::decode) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    println!("invalid message: {}", e);
+                    continue;
+                }
+            };
+            let hash = item.content_hash();
+            if synced.is_current(selection, &hash) {
+                continue;
+            }
+            if !selections.contains(selection) || !clipboard.supports(selection) {
+                continue;
+            }
+            recorder.record(&msg);
+
+            // `ClipboardListener` sends it to other followers once clipboard reports the change
+            synced.received(selection, hash, &id);
+            if let Err(e) = clipboard.set(selection, item) {
+                synced.forget(selection);
+                println!("{}", e);
+            }
+        }
+    }
+
+    async fn relay_stream(mut stream: Incoming, id: String, relay: Relay, recorder: Recorder) {
+        while let Ok(Some(msg)) = stream.message().await {
+            let revision = recorder.record(&msg);
+            relay.broadcast(&id, Message { revision, ..msg });
+        }
+    }
+
+    async fn mesh_stream(mut stream: Incoming, id: String, mesh: Mesh) {
+        while let Ok(Some(msg)) = stream.message().await {
+            mesh.receive(&id, msg).await;
         }
     }
 }
@@ -27,57 +233,132 @@ impl ClipboardServiceImpl {
 impl message::clipboard_service_server::ClipboardService for ClipboardServiceImpl {
     type ChangedStream = ReceiverStream<Result<message::Message, Status>>;
 
+    #[allow(clippy::result_large_err)]
+    async fn handshake(
+        &self,
+        request: Request<message::Hello>,
+    ) -> Result<Response<message::Welcome>, Status> {
+        let addr = request.remote_addr();
+        let token = auth::bearer(&request).map(str::to_owned);
+        let hello = request.into_inner();
+        let admitted = self
+            .config
+            .admit(handshake::channel(&hello), token.as_deref())
+            .and_then(|_| self.sessions.open(hello));
+        match admitted {
+            Ok(session_id) => Ok(Response::new(message::Welcome {
+                session_id,
+                server: Some(handshake::hello()),
+            })),
+            Err(status) => {
+                println!("refused connection from {:?}: {}", addr, status.message());
+                Err(status)
+            }
+        }
+    }
+
     async fn changed(
         &self,
         request: Request<Streaming<message::Message>>,
     ) -> Result<Response<Self::ChangedStream>, Status> {
-        if let Some(addr) = request.remote_addr() {
-            println!(
-                "new connection received: {}:{}",
-                addr.ip().to_string(),
-                addr.port()
-            );
-        }
-        let mut stream = request.into_inner();
+        let hello = self.sessions.take(&request)?;
+        self.config
+            .admit(handshake::channel(&hello), auth::bearer(&request))?;
+        let addr = request.remote_addr();
+        let stream = Incoming::new(request.into_inner());
         let (tx, rx) = tokio::sync::mpsc::channel(32);
-        let (otx, orx) = tokio::sync::oneshot::channel::<()>();
 
-        let follower = Follower::new(tx, orx);
-        let sender = self.sender.clone();
+        let channel = handshake::channel(&hello).to_owned();
+        let backend = match &self.backend {
+            Backend::Clipboard {
+                channel: own,
+                others,
+                ..
+            } if *own != channel => Backend::Relay(others.clone()),
+            backend => backend.clone(),
+        };
+        let recorder = Recorder {
+            history: self.history.clone(),
+            latest: self.latest.clone(),
+            hostname: hello.hostname.clone(),
+            channel: channel.clone(),
+        };
+        let mut follower = Follower::new(tx, &hello, addr, self.queue);
+        // queued before any live change, so the newer one wins
+        if let Some(message) = self.late_join_item(&channel, &hello) {
+            follower.send(Ok(message));
+        }
         let id = follower.id().clone();
+        // follower leaves as soon as its stream ends
+        let registry = self.registry();
+        registry.join(follower);
+
         tokio::spawn(async move {
-            let mut clipboard = Clipboard::new().unwrap();
-            while let Ok(Some(msg)) = stream.message().await {
-                match msg.r#type.as_ref() {
-                    "text" => {
-                        if let Ok(text) = String::from_utf8(msg.body) {
-                            sender.send(id.clone()).ok();
-                            clipboard.set_text(text).ok();
-                        }
-                    }
-                    "image" => {
-                        let w: [u8; 4] = msg.body[0..4].to_vec().try_into().unwrap();
-                        let h: [u8; 4] = msg.body[4..8].to_vec().try_into().unwrap();
-                        let width = u32::from_le_bytes(w) as usize;
-                        let height = u32::from_le_bytes(h) as usize;
-                        let image_data = ImageData {
-                            width,
-                            height,
-                            bytes: Cow::from(&msg.body[8..]),
-                        };
-                        clipboard.set_image(image_data).ok();
-                    }
-                    _ => {
-                        println!("not supported type: {}", msg.r#type);
-                    }
+            match backend {
+                Backend::Clipboard {
+                    clipboard,
+                    synced,
+                    selections,
+                    cipher,
+                    ..
+                } => {
+                    Self::apply(
+                        stream,
+                        id.clone(),
+                        clipboard,
+                        synced,
+                        selections,
+                        cipher,
+                        recorder,
+                    )
+                    .await
+                }
+                Backend::Relay(relay) => {
+                    Self::relay_stream(stream, id.clone(), relay, recorder).await
                 }
+                Backend::Mesh(mesh) => Self::mesh_stream(stream, id.clone(), mesh).await,
             }
-            otx.send(()).ok();
-            println!("connection closed");
+            registry.leave(&id);
         });
 
-        self.follower_sender.send(follower).ok();
-
         Ok(Response::new(ReceiverStream::new(rx)))
     }
+
+    async fn list_history(
+        &self,
+        request: Request<message::ListHistoryRequest>,
+    ) -> Result<Response<message::History>, Status> {
+        let channel = self.admit(&request, &request.get_ref().channel)?;
+        let limit = match request.get_ref().limit {
+            0 => HISTORY_PAGE,
+            limit => limit as usize,
+        };
+        Ok(Response::new(message::History {
+            entries: self.history.list(channel, limit),
+        }))
+    }
+
+    async fn get_entry(
+        &self,
+        request: Request<message::GetEntryRequest>,
+    ) -> Result<Response<message::HistoryEntry>, Status> {
+        let channel = self.admit(&request, &request.get_ref().channel)?;
+        let id = request.get_ref().id;
+        self.history
+            .get(channel, id)
+            .map(Response::new)
+            .ok_or_else(|| Status::not_found(format!("no history entry: {}", id)))
+    }
+
+    async fn clear_history(
+        &self,
+        request: Request<message::ClearHistoryRequest>,
+    ) -> Result<Response<message::ClearHistoryResponse>, Status> {
+        let channel = self.admit(&request, &request.get_ref().channel)?;
+        let removed = self.history.clear(channel);
+        if let Some(addr) = request.remote_addr() {
+            println!("history of channel {} cleared by: {}", channel, addr);
+        }
+        Ok(Response::new(message::ClearHistoryResponse { removed }))
+    }
 }
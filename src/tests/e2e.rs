@@ -0,0 +1,417 @@
+//! Server and clients syncing in-memory clipboards over loopback.
+
+use crate::client::{ClipboardClient, Endpoint, Target};
+use crate::clipboard::{ClipboardBackend, MemoryClipboard, Selection, SelectionMode};
+use crate::config::ServerConfig;
+use crate::crypto::Cipher;
+use crate::handshake::{self, DEFAULT_CHANNEL};
+use crate::history::History;
+use crate::payload::{ClipboardItem, Payload};
+use crate::server::registry::{Event, Registry};
+use crate::server::server::{ClipboardServiceImpl, LateJoin};
+use crate::tls::{ClientTlsArgs, ServerTlsArgs};
+use arboard::ImageData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Code, Status};
+
+/// How long content may take to reach the other side.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for content that should never arrive.
+const SETTLE: Duration = Duration::from_millis(300);
+
+/// Starts server syncing `clipboard`, returns its address and connected followers.
+///
+/// Late join is off, pushed content would race with changes made by tests.
+fn start_server(clipboard: Arc<MemoryClipboard>) -> (String, Registry) {
+    start_server_with(clipboard, ServerConfig::default(), LateJoin::Off)
+}
+
+/// Same as `start_server`, peers may only join channels listed in `config` and get content
+/// as `late_join` says.
+fn start_server_with(
+    clipboard: Arc<MemoryClipboard>,
+    config: ServerConfig,
+    late_join: LateJoin,
+) -> (String, Registry) {
+    let service = crate::local_service(
+        clipboard,
+        SelectionMode::Clipboard,
+        DEFAULT_CHANNEL.to_owned(),
+        Cipher::new(None).unwrap(),
+        History::default(),
+    )
+    .with_config(config)
+    .with_late_join(late_join);
+    let registry = service.registry();
+    (serve(service, ServerTlsArgs::default()), registry)
+}
+
+/// Serves `service` on a free loopback port, returns its address.
+fn serve(service: ClipboardServiceImpl, tls: ServerTlsArgs) -> String {
+    let incoming = TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = incoming.local_addr().unwrap();
+    tokio::spawn(async move {
+        crate::serve_incoming(service, incoming, vec![], tls, false)
+            .await
+            .unwrap();
+    });
+    addr.to_string()
+}
+
+fn start_client(addr: &str, clipboard: Arc<MemoryClipboard>) -> JoinHandle<Result<(), Code>> {
+    join(addr, DEFAULT_CHANNEL, None, clipboard)
+}
+
+/// Starts client of `channel` presenting `token`, the task ends with code of the status server
+/// refused it with.
+fn join(
+    addr: &str,
+    channel: &str,
+    token: Option<&str>,
+    clipboard: Arc<MemoryClipboard>,
+) -> JoinHandle<Result<(), Code>> {
+    let endpoint = Endpoint {
+        target: Target::Address(addr.to_owned()),
+        token: token.map(str::to_owned),
+        channel: channel.to_owned(),
+        tls: None,
+        gzip: false,
+    };
+    let client = ClipboardClient::new(
+        endpoint,
+        clipboard,
+        SelectionMode::Clipboard,
+        Cipher::new(None).unwrap(),
+    );
+    tokio::spawn(async move {
+        client
+            .start()
+            .await
+            .map_err(|e| match e.downcast_ref::<Status>() {
+                Some(status) => status.code(),
+                None => panic!("client failed: {}", e),
+            })
+    })
+}
+
+/// Server with two connected clients and nothing in flight.
+///
+/// Clients join one by one, each is connected once the server pastes what it copied.
+async fn start() -> [Arc<MemoryClipboard>; 3] {
+    let clipboards: [Arc<MemoryClipboard>; 3] = Default::default();
+    let [server, first, second] = &clipboards;
+    let (addr, _) = start_server(server.clone());
+    wait(|| server.is_watched()).await;
+
+    start_client(&addr, first.clone());
+    wait(|| first.is_watched()).await;
+    copy(first, "first joined");
+    wait(|| pasted(server).as_deref() == Some("first joined")).await;
+
+    start_client(&addr, second.clone());
+    wait(|| second.is_watched()).await;
+    copy(second, "second joined");
+    wait(|| pasted(server).as_deref() == Some("second joined")).await;
+    wait(|| pasted(first).as_deref() == Some("second joined")).await;
+    clipboards
+}
+
+async fn wait(condition: impl Fn() -> bool) {
+    let waiting = async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    };
+    tokio::time::timeout(TIMEOUT, waiting)
+        .await
+        .expect("content never arrived");
+}
+
+fn copy(clipboard: &MemoryClipboard, text: &str) {
+    let item = ClipboardItem::from(Payload::Text(text.to_owned()));
+    clipboard.set(Selection::Clipboard, item).unwrap();
+}
+
+fn pasted(clipboard: &MemoryClipboard) -> Option<String> {
+    clipboard.text(Selection::Clipboard)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_change_reaches_server_and_other_client() {
+    let [server, first, second] = start().await;
+
+    copy(&first, "from client");
+    wait(|| pasted(&server).as_deref() == Some("from client")).await;
+    wait(|| pasted(&second).as_deref() == Some("from client")).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn server_change_reaches_every_client() {
+    let [server, first, second] = start().await;
+
+    copy(&server, "from server");
+    wait(|| pasted(&first).as_deref() == Some("from server")).await;
+    wait(|| pasted(&second).as_deref() == Some("from server")).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn later_change_wins() {
+    let [server, first, second] = start().await;
+
+    copy(&first, "first");
+    wait(|| pasted(&second).as_deref() == Some("first")).await;
+    copy(&second, "second");
+    wait(|| pasted(&server).as_deref() == Some("second")).await;
+    wait(|| pasted(&first).as_deref() == Some("second")).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn richest_representation_is_pasted() {
+    let [server, first, second] = start().await;
+
+    let item = ClipboardItem::from_iter([
+        Payload::Text("bold".to_owned()),
+        Payload::Html {
+            html: "<b>bold</b>".to_owned(),
+            alt_text: "bold".to_owned(),
+        },
+    ]);
+    first.set(Selection::Clipboard, item).unwrap();
+    let html = |clipboard: &MemoryClipboard| match clipboard.get(Selection::Clipboard)?.richest()? {
+        Payload::Html { html, .. } => Some(html.clone()),
+        _ => None,
+    };
+    wait(|| html(&server).as_deref() == Some("<b>bold</b>")).await;
+    wait(|| html(&second).as_deref() == Some("<b>bold</b>")).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn every_representation_arrives() {
+    let [server, first, second] = start().await;
+
+    let item = ClipboardItem::from_iter([
+        Payload::Text("red".to_owned()),
+        Payload::Html {
+            html: "<font color=red>red</font>".to_owned(),
+            alt_text: "red".to_owned(),
+        },
+        Payload::Image(ImageData {
+            width: 2,
+            height: 1,
+            bytes: vec![255, 0, 0, 255, 255, 0, 0, 255].into(),
+        }),
+    ]);
+    first.set(Selection::Clipboard, item).unwrap();
+    let types = |clipboard: &MemoryClipboard| {
+        let item = clipboard.get(Selection::Clipboard)?;
+        Some(item.representations().keys().copied().collect::<Vec<_>>())
+    };
+    let expected = ["image/png", "text/html", "text/plain;charset=utf-8"];
+    wait(|| types(&server).as_deref() == Some(&expected[..])).await;
+    wait(|| types(&second).as_deref() == Some(&expected[..])).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn later_change_wins_over_late_join_item() {
+    let server = Arc::new(MemoryClipboard::default());
+    let client = Arc::new(MemoryClipboard::default());
+    let (addr, registry) =
+        start_server_with(server.clone(), ServerConfig::default(), LateJoin::Push);
+    wait(|| server.is_watched()).await;
+    copy(&server, "old");
+    tokio::time::sleep(SETTLE).await;
+
+    start_client(&addr, client.clone());
+    wait(|| registry.connections().len() == 1).await;
+    copy(&server, "new");
+    wait(|| pasted(&client).as_deref() == Some("new")).await;
+    tokio::time::sleep(SETTLE).await;
+    assert_eq!(pasted(&client).as_deref(), Some("new"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn received_content_is_not_sent_back() {
+    let clipboards = start().await;
+    let [server, first, second] = &clipboards;
+    let writes = clipboards.each_ref().map(|clipboard| clipboard.writes());
+
+    copy(first, "once");
+    wait(|| pasted(server).as_deref() == Some("once")).await;
+    wait(|| pasted(second).as_deref() == Some("once")).await;
+    tokio::time::sleep(SETTLE).await;
+    assert_eq!(
+        clipboards.map(|clipboard| clipboard.writes()),
+        writes.map(|w| w + 1)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn same_content_is_sent_once() {
+    let clipboards = start().await;
+    let [server, first, second] = &clipboards;
+
+    copy(server, "again");
+    wait(|| pasted(first).as_deref() == Some("again")).await;
+    wait(|| pasted(second).as_deref() == Some("again")).await;
+    let writes = clipboards.each_ref().map(|clipboard| clipboard.writes());
+    copy(server, "again");
+    tokio::time::sleep(SETTLE).await;
+    assert_eq!(
+        clipboards.each_ref().map(|c| c.writes()),
+        [writes[0] + 1, writes[1], writes[2]]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn disconnected_client_leaves_at_once() {
+    let [server, client]: [Arc<MemoryClipboard>; 2] = Default::default();
+    let (addr, registry) = start_server(server.clone());
+    let mut events = registry.subscribe();
+    let task = start_client(&addr, client.clone());
+    let event = tokio::time::timeout(TIMEOUT, events.recv()).await.unwrap();
+    let Ok(Event::Joined(joined)) = event else {
+        panic!("unexpected event: {:?}", event);
+    };
+    wait(|| client.is_watched()).await;
+    copy(&server, "before leaving");
+    wait(|| pasted(&client).as_deref() == Some("before leaving")).await;
+
+    // nothing is sent to it anymore
+    task.abort();
+    let event = tokio::time::timeout(TIMEOUT, events.recv()).await.unwrap();
+    let Ok(Event::Left(left)) = event else {
+        panic!("unexpected event: {:?}", event);
+    };
+    assert_eq!(left.id, joined.id);
+    assert_eq!(left.stats.sent, 1);
+    assert!(left.stats.bytes > 0);
+    assert!(registry.connections().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_channel_next_to_protected_one() {
+    let config = toml::from_str(
+        r#"
+        [channels.default]
+
+        [channels.design]
+        tokens = ["design-secret"]
+        "#,
+    )
+    .unwrap();
+    let [server, open, designer, colleague, intruder]: [Arc<MemoryClipboard>; 5] =
+        Default::default();
+    let (addr, registry) = start_server_with(server.clone(), config, LateJoin::Off);
+    wait(|| server.is_watched()).await;
+
+    join(&addr, DEFAULT_CHANNEL, None, open.clone());
+    wait(|| open.is_watched()).await;
+    copy(&open, "no token needed");
+    wait(|| pasted(&server).as_deref() == Some("no token needed")).await;
+
+    join(&addr, "design", Some("design-secret"), designer.clone());
+    join(&addr, "design", Some("design-secret"), colleague.clone());
+    let intruder = join(&addr, "design", Some("wrong"), intruder.clone());
+    let refused = tokio::time::timeout(TIMEOUT, intruder).await.unwrap();
+    assert_eq!(refused.unwrap(), Err(Code::PermissionDenied));
+    let joined = |channel: &str| {
+        registry
+            .connections()
+            .iter()
+            .filter(|connection| connection.channel == channel)
+            .count()
+    };
+    wait(|| joined("design") == 2).await;
+    assert_eq!(joined(DEFAULT_CHANNEL), 1);
+
+    // content stays in its channel both ways
+    wait(|| designer.is_watched()).await;
+    copy(&designer, "design only");
+    wait(|| pasted(&colleague).as_deref() == Some("design only")).await;
+    tokio::time::sleep(SETTLE).await;
+    assert_eq!(pasted(&open).as_deref(), Some("no token needed"));
+    assert_eq!(pasted(&server).as_deref(), Some("no token needed"));
+
+    copy(&open, "default only");
+    wait(|| pasted(&server).as_deref() == Some("default only")).await;
+    tokio::time::sleep(SETTLE).await;
+    assert_eq!(pasted(&designer).as_deref(), Some("design only"));
+    assert_eq!(pasted(&colleague).as_deref(), Some("design only"));
+}
+
+/// Certificate fixture made by `tls/generate.sh`.
+fn certificate(name: &str) -> Option<PathBuf> {
+    Some(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/tests/tls")
+            .join(name),
+    )
+}
+
+/// Server with certificate for `localhost`, requiring client certificates issued by the test
+/// CA when `mutual`.
+fn start_tls_server(mutual: bool) -> String {
+    let service = crate::local_service(
+        Arc::new(MemoryClipboard::default()),
+        SelectionMode::Clipboard,
+        DEFAULT_CHANNEL.to_owned(),
+        Cipher::new(None).unwrap(),
+        History::default(),
+    );
+    let tls = ServerTlsArgs {
+        tls_cert: certificate("server.pem"),
+        tls_key: certificate("server.key"),
+        tls_client_ca: if mutual { certificate("ca.pem") } else { None },
+    };
+    serve(service, tls).replace("127.0.0.1", "localhost")
+}
+
+/// Client trusting the test CA, presenting certificate `client` if given.
+fn tls_client(client: Option<&str>) -> ClientTlsArgs {
+    ClientTlsArgs {
+        tls_ca: certificate("ca.pem"),
+        tls_cert: client.and_then(|name| certificate(&format!("{}.pem", name))),
+        tls_key: client.and_then(|name| certificate(&format!("{}.key", name))),
+        tls_domain: None,
+    }
+}
+
+/// Greets server at `addr` over TLS, returns whether it answered.
+async fn greet(addr: &str, tls: ClientTlsArgs) -> bool {
+    let endpoint = Endpoint {
+        target: Target::Address(addr.to_owned()),
+        token: None,
+        channel: DEFAULT_CHANNEL.to_owned(),
+        tls: tls.config(addr).unwrap(),
+        gzip: false,
+    };
+    let greeting = async {
+        let mut client = endpoint.connect(addr).await?;
+        client.handshake(handshake::hello()).await?;
+        Ok::<_, Box<dyn std::error::Error>>(())
+    };
+    tokio::time::timeout(TIMEOUT, greeting)
+        .await
+        .unwrap()
+        .is_ok()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_trusting_server_ca_connects() {
+    let addr = start_tls_server(false);
+    assert!(greet(&addr, tls_client(None)).await);
+    assert!(greet(&addr, tls_client(Some("client"))).await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_without_trusted_certificate_is_rejected() {
+    let addr = start_tls_server(true);
+    assert!(greet(&addr, tls_client(Some("client"))).await);
+    assert!(!greet(&addr, tls_client(None)).await);
+    assert!(!greet(&addr, tls_client(Some("stranger"))).await);
+}
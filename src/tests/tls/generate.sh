@@ -0,0 +1,38 @@
+#!/bin/sh
+# Regenerates certificates of TLS tests: CA, server certificate for localhost, client
+# certificate issued by the CA and one issued by an unrelated CA.
+set -e
+cd "$(dirname "$0")"
+days=36500
+
+key() {
+    openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "$1.key"
+}
+
+ca() {
+    key "$1"
+    openssl req -x509 -new -key "$1.key" -subj "/CN=$2" -days $days -out "$1.pem" \
+        -addext basicConstraints=critical,CA:true -addext keyUsage=critical,keyCertSign
+}
+
+# issue NAME CA SUBJECT EXTENSIONS
+issue() {
+    key "$1"
+    openssl req -new -key "$1.key" -subj "/CN=$3" -out "$1.csr"
+    printf '%s\n' "$4" > "$1.ext"
+    openssl x509 -req -in "$1.csr" -CA "$2.pem" -CAkey "$2.key" -CAcreateserial -days $days \
+        -extfile "$1.ext" -out "$1.pem"
+    rm "$1.csr" "$1.ext"
+}
+
+ca ca "lanclip test CA"
+ca other-ca "unrelated CA"
+issue server ca localhost \
+    "basicConstraints=CA:false
+subjectAltName=DNS:localhost,IP:127.0.0.1
+extendedKeyUsage=serverAuth"
+issue client ca client "basicConstraints=CA:false
+extendedKeyUsage=clientAuth"
+issue stranger other-ca stranger "basicConstraints=CA:false
+extendedKeyUsage=clientAuth"
+rm -f ca.key other-ca.key other-ca.pem ./*.srl
@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+mod tls;
+
+pub use tls::{ClientTlsArgs, PeerTlsArgs, ServerTlsArgs};
@@ -0,0 +1,119 @@
+use clap::Args;
+use std::fs;
+use std::path::PathBuf;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct ServerTlsArgs {
+    /// PEM certificate of the server, enables TLS together with `--tls-key`
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of the server certificate
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// PEM CA certificate, clients must present certificate issued by it (mutual TLS)
+    #[arg(long, requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+}
+
+impl ServerTlsArgs {
+    /// Builds TLS config, `None` means plaintext transport.
+    pub fn config(&self) -> Result<Option<ServerTlsConfig>, Box<dyn std::error::Error>> {
+        let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) else {
+            return Ok(None);
+        };
+
+        let mut config =
+            ServerTlsConfig::new().identity(Identity::from_pem(fs::read(cert)?, fs::read(key)?));
+        if let Some(ca) = &self.tls_client_ca {
+            config = config.client_ca_root(Certificate::from_pem(fs::read(ca)?));
+        }
+        Ok(Some(config))
+    }
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct ClientTlsArgs {
+    /// PEM CA certificate the server certificate is verified against, enables TLS
+    #[arg(long)]
+    pub tls_ca: Option<PathBuf>,
+    /// PEM client certificate, for servers requiring mutual TLS
+    #[arg(long, requires_all = ["tls_ca", "tls_key"])]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of the client certificate
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Name expected in server certificate, defaults to host of the server address
+    #[arg(long, requires = "tls_ca")]
+    pub tls_domain: Option<String>,
+}
+
+impl ClientTlsArgs {
+    /// Builds TLS config for connecting to `host`, `None` means plaintext transport.
+    pub fn config(
+        &self,
+        host: &str,
+    ) -> Result<Option<ClientTlsConfig>, Box<dyn std::error::Error>> {
+        let Some(ca) = &self.tls_ca else {
+            return Ok(None);
+        };
+
+        let domain = match &self.tls_domain {
+            Some(domain) => domain.clone(),
+            None => host
+                .rsplit_once(':')
+                .map_or(host, |(domain, _)| domain)
+                .trim_matches(['[', ']'])
+                .to_owned(),
+        };
+        if domain.is_empty() {
+            return Err("--tls-domain is required when server address is not given".into());
+        }
+        let mut config = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(fs::read(ca)?))
+            .domain_name(domain);
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            config = config.identity(Identity::from_pem(fs::read(cert)?, fs::read(key)?));
+        }
+        Ok(Some(config))
+    }
+}
+
+/// TLS of a mesh node, its certificate is served to nodes linking to it and presented to nodes
+/// it links to, so every link is mutually authenticated.
+#[derive(Args, Debug, Clone, Default)]
+pub struct PeerTlsArgs {
+    /// PEM certificate of this node, enables TLS together with `--tls-key` and `--tls-ca`
+    #[arg(long, requires_all = ["tls_key", "tls_ca"])]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of the node certificate
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// PEM CA certificate, certificates of other nodes must be issued by it
+    #[arg(long, requires = "tls_cert")]
+    pub tls_ca: Option<PathBuf>,
+    /// Name expected in certificates of other nodes, defaults to host of their address
+    #[arg(long, requires = "tls_cert")]
+    pub tls_domain: Option<String>,
+}
+
+impl PeerTlsArgs {
+    /// Options of links accepted by this node.
+    pub fn server(&self) -> ServerTlsArgs {
+        ServerTlsArgs {
+            tls_cert: self.tls_cert.clone(),
+            tls_key: self.tls_key.clone(),
+            tls_client_ca: self.tls_ca.clone(),
+        }
+    }
+
+    /// Options of links this node dials.
+    pub fn client(&self) -> ClientTlsArgs {
+        ClientTlsArgs {
+            tls_ca: self.tls_ca.clone(),
+            tls_cert: self.tls_cert.clone(),
+            tls_key: self.tls_key.clone(),
+            tls_domain: self.tls_domain.clone(),
+        }
+    }
+}